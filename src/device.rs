@@ -12,22 +12,29 @@
 //!     }
 //! }
 //! ```
-use std::fmt;
-use crate::util::check_multimedia_error;
+use crate::util::check_multimedia_error_ctx;
+use crate::wave;
 use crate::Error;
-use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::mem::{self, MaybeUninit};
+use std::ptr;
 use widestring::U16CString;
 use winapi::um::mmeapi::*;
 use winapi::um::mmsystem::*;
 
-/// Used to select a waveform-audio output device capable of playing the given format.
-pub use winapi::um::mmsystem::WAVE_MAPPER;
+/// Sentinel device identifier used to select a waveform-audio output device capable of playing
+/// the given format, instead of a specific one.
+///
+/// It is not in the range `0..count()`, but it is accepted anywhere a device identifier is, such
+/// as `get_capabilities` (to query the capabilities of the mapper itself) and `Out::open`.
+pub const WAVE_MAPPER: u32 = winapi::um::mmsystem::WAVE_MAPPER;
 
 // Constants from "shared/mmreg.h".
-enum_with_try_from!(
+enum_with_fallback!(
 /// Product identifier for a device.
-pub enum Product(u16) {
+#[non_exhaustive]
+pub enum Product(u16, Unknown) {
     /// Adlib-compatible synthesizer.
     Adlib = 9,
     /// G.711 codec.
@@ -104,9 +111,13 @@ pub enum Product(u16) {
     WaveMapper = 2,
 });
 
-enum_with_try_from!(
+enum_with_fallback!(
 /// Manufacturer for the device driver for a device.
-pub enum Manufacturer(u16) {
+///
+/// Third-party drivers commonly report identifiers which are not in this list, in which case
+/// the identifier is kept in `Manufacturer::Unknown`.
+#[non_exhaustive]
+pub enum Manufacturer(u16, Unknown) {
     /// Advanced Gravis Computer Technology, Ltd.
     Gravis = 34,
     /// Antex Electronics Corporation.
@@ -252,7 +263,12 @@ pub enum Manufacturer(u16) {
 });
 
 /// Standard device formats.
+///
+/// These only cover 8-bit and 16-bit PCM data at a few sample rates, which is all that
+/// `Capabilities` can report. Devices may support other formats, such as 32-bit IEEE float, and
+/// `is_format_supported` can be used to ask the device directly about any of them.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u32)]
 pub enum Format {
     /// 11.025 kHz, mono, 8-bit.
@@ -289,8 +305,93 @@ pub enum Format {
     Stereo16b96Khz = WAVE_FORMAT_96S16,
 }
 
+impl Format {
+    /// Every standard device format, in the order of their bits.
+    ///
+    /// ```
+    /// use winaudio::device::Format;
+    ///
+    /// assert_eq!(Format::all().len(), 16);
+    /// assert_eq!(Format::all()[0], Format::Mono8b11Khz);
+    /// ```
+    pub const fn all() -> [Format; 16] {
+        [
+            Format::Mono8b11Khz,
+            Format::Mono16b11Khz,
+            Format::Stereo8b11Khz,
+            Format::Stereo16b11Khz,
+            Format::Mono8b22Khz,
+            Format::Mono16b22Khz,
+            Format::Stereo8b22Khz,
+            Format::Stereo16b22Khz,
+            Format::Mono8b44Khz,
+            Format::Mono16b44Khz,
+            Format::Stereo8b44Khz,
+            Format::Stereo16b44Khz,
+            Format::Mono8b96Khz,
+            Format::Mono16b96Khz,
+            Format::Stereo8b96Khz,
+            Format::Stereo16b96Khz,
+        ]
+    }
+
+    /// Sample rate of the format, in hertz.
+    ///
+    /// ```
+    /// use winaudio::device::Format;
+    ///
+    /// let fmt = Format::Stereo16b44Khz;
+    /// assert_eq!(fmt.sample_rate(), 44100);
+    /// assert_eq!(fmt.channels(), 2);
+    /// assert_eq!(fmt.bits_per_sample(), 16);
+    /// ```
+    pub fn sample_rate(self) -> u32 {
+        self.params().1
+    }
+
+    /// Number of channels of the format.
+    pub fn channels(self) -> u16 {
+        self.params().0
+    }
+
+    /// Bits per sample of the format.
+    pub fn bits_per_sample(self) -> u16 {
+        self.params().2
+    }
+
+    /// The channels, sample rate and bits per sample of the format.
+    fn params(self) -> (u16, u32, u16) {
+        match self {
+            Self::Mono8b11Khz => (1, 11025, 8),
+            Self::Mono16b11Khz => (1, 11025, 16),
+            Self::Stereo8b11Khz => (2, 11025, 8),
+            Self::Stereo16b11Khz => (2, 11025, 16),
+            Self::Mono8b22Khz => (1, 22050, 8),
+            Self::Mono16b22Khz => (1, 22050, 16),
+            Self::Stereo8b22Khz => (2, 22050, 8),
+            Self::Stereo16b22Khz => (2, 22050, 16),
+            Self::Mono8b44Khz => (1, 44100, 8),
+            Self::Mono16b44Khz => (1, 44100, 16),
+            Self::Stereo8b44Khz => (2, 44100, 8),
+            Self::Stereo16b44Khz => (2, 44100, 16),
+            Self::Mono8b96Khz => (1, 96000, 8),
+            Self::Mono16b96Khz => (1, 96000, 16),
+            Self::Stereo8b96Khz => (2, 96000, 8),
+            Self::Stereo16b96Khz => (2, 96000, 16),
+        }
+    }
+}
+
+impl From<Format> for wave::Format {
+    /// Describe the standard device format as PCM data.
+    fn from(fmt: Format) -> Self {
+        wave::Format::pcm(fmt.channels(), fmt.sample_rate(), fmt.bits_per_sample())
+    }
+}
+
 /// Additional functionality a device may provide.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u32)]
 pub enum Functionality {
     /// Supports separate left and right volume control.
@@ -316,16 +417,25 @@ pub struct Capabilities {
 impl Capabilities {
     /// Manufacturer for the device driver for the device.
     pub fn manufacturer(&self) -> Manufacturer {
-        Manufacturer::try_from(self.caps.wMid).expect("unknown manufacturer")
+        self.caps.wMid.into()
     }
 
-    /// Product identifier for the device.
+    /// Product identifier for the device, or `None` if it's not known. Only the products from
+    /// Microsoft are known.
     pub fn product(&self) -> Option<Product> {
+        match self.product_or_unknown() {
+            Product::Unknown(_) => None,
+            product => Some(product),
+        }
+    }
+
+    /// Like `product`, but products that are not known are kept in `Product::Unknown`.
+    pub fn product_or_unknown(&self) -> Product {
         // The "mmreg.h" header file contains duplicate identifiers for different products, so
         // it's impossible to have a single enumeration with all of them. Instead, only these:
         // https://docs.microsoft.com/en-us/windows/win32/multimedia/microsoft-corporation-product-identifiers
         // ...are included.
-        Product::try_from(self.caps.wPid).ok()
+        self.caps.wPid.into()
     }
 
     /// Version number of the device driver for the device.
@@ -337,34 +447,35 @@ impl Capabilities {
 
     /// Product name.
     pub fn name(&self) -> String {
-        let name = unsafe { U16CString::from_ptr_str(self.caps.szPname.as_ptr()) };
+        // Copy the field out first, as references to fields of packed structures are unaligned.
+        let pname = self.caps.szPname;
+        let name = unsafe { U16CString::from_ptr_str(pname.as_ptr()) };
         name.to_string().expect("non-utf8 product name")
     }
 
     /// Standard formats that are supported.
     pub fn supported_formats(&self) -> Vec<Format> {
-        [
-            Format::Mono8b11Khz,
-            Format::Mono16b11Khz,
-            Format::Stereo8b11Khz,
-            Format::Stereo16b11Khz,
-            Format::Mono8b22Khz,
-            Format::Mono16b22Khz,
-            Format::Stereo8b22Khz,
-            Format::Stereo16b22Khz,
-            Format::Mono8b44Khz,
-            Format::Mono16b44Khz,
-            Format::Stereo8b44Khz,
-            Format::Stereo16b44Khz,
-            Format::Mono8b96Khz,
-            Format::Mono16b96Khz,
-            Format::Stereo8b96Khz,
-            Format::Stereo16b96Khz,
-        ]
-        .iter()
-        .copied()
-        .filter(|f| (self.caps.dwFormats & *f as u32) != 0)
-        .collect()
+        Format::all()
+            .iter()
+            .copied()
+            .filter(|f| (self.caps.dwFormats & *f as u32) != 0)
+            .collect()
+    }
+
+    /// Whether the given format is one of the supported standard formats. Returns `None` if it's
+    /// not a standard format at all, such as 32-bit IEEE float, as the capabilities can't tell
+    /// whether the device supports it. Use `is_format_supported` to find out in that case.
+    pub fn supports_format(&self, fmt: &wave::Format) -> Option<bool> {
+        Format::all()
+            .iter()
+            .find(|f| wave::Format::from(**f) == *fmt)
+            .map(|f| (self.caps.dwFormats & *f as u32) != 0)
+    }
+
+    /// Unmasked bitmask of the standard formats that are supported, including any bits that
+    /// don't correspond to a known `Format`.
+    pub fn raw_formats(&self) -> u32 {
+        self.caps.dwFormats
     }
 
     /// Number specifying whether the device supports mono (1) or stereo (2) output.
@@ -372,6 +483,27 @@ impl Capabilities {
         self.caps.wChannels
     }
 
+    /// Highest sample rate among the supported standard formats, or `None` if there are none.
+    pub fn max_sample_rate(&self) -> Option<u32> {
+        self.supported_formats()
+            .into_iter()
+            .map(|f| wave::Format::from(f).samples_per_sec)
+            .max()
+    }
+
+    /// Highest bit depth among the supported standard formats, or `None` if there are none.
+    pub fn max_bits_per_sample(&self) -> Option<u16> {
+        self.supported_formats()
+            .into_iter()
+            .map(|f| wave::Format::from(f).bits_per_sample)
+            .max()
+    }
+
+    /// Highest number of channels the device supports. This is the same as `channels`.
+    pub fn max_channels(&self) -> u16 {
+        self.caps.wChannels
+    }
+
     /// Optional functionality supported by the device.
     pub fn functionality(&self) -> Vec<Functionality> {
         [
@@ -387,13 +519,75 @@ impl Capabilities {
         .filter(|f| (self.caps.dwSupport & *f as u32) != 0)
         .collect()
     }
+
+    /// Whether the device supports the given optional functionality.
+    pub fn supports(&self, f: Functionality) -> bool {
+        (self.caps.dwSupport & f as u32) != 0
+    }
+
+    /// Whether the device supports all of the given optional functionality. This is `true` if
+    /// `fs` is empty.
+    pub fn supports_all(&self, fs: &[Functionality]) -> bool {
+        fs.iter().all(|f| self.supports(*f))
+    }
+
+    /// Unmasked bitmask of the optional functionality supported by the device, including any
+    /// bits that don't correspond to a known `Functionality`.
+    pub fn raw_support(&self) -> u32 {
+        self.caps.dwSupport
+    }
+
+    /// Copy of the raw structure returned by Windows, for advanced uses that need a field that's
+    /// not otherwise exposed.
+    ///
+    /// The structure is packed, so its fields should be copied out before being used, as taking
+    /// references to them is not allowed.
+    pub fn as_raw(&self) -> WAVEOUTCAPSW {
+        self.caps
+    }
+}
+
+impl Capabilities {
+    /// The fields that tell devices apart, copied out of the raw structure. The name is cut at
+    /// its null terminator, so that whatever the driver left after it is not compared.
+    fn key(&self) -> (u16, u16, [u16; 32], u32, u16, u32) {
+        let mut name = self.caps.szPname;
+        if let Some(end) = name.iter().position(|&c| c == 0) {
+            name[end..].iter_mut().for_each(|c| *c = 0);
+        }
+        (
+            self.caps.wMid,
+            self.caps.wPid,
+            name,
+            self.caps.dwFormats,
+            self.caps.wChannels,
+            self.caps.dwSupport,
+        )
+    }
+}
+
+/// Capabilities are equal if their manufacturer, product, name, supported formats, channels
+/// and functionality are, so that a device can be recognized between two queries. The driver
+/// version is not compared.
+impl PartialEq for Capabilities {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Capabilities {}
+
+impl Hash for Capabilities {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
 }
 
 impl fmt::Debug for Capabilities {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Capabilities")
             .field("manufacturer", &self.manufacturer())
-            .field("product", &self.product())
+            .field("product", &self.product_or_unknown())
             .field("driver_version", &self.driver_version())
             .field("name", &self.name())
             .field("supported_formats", &self.supported_formats())
@@ -403,8 +597,35 @@ impl fmt::Debug for Capabilities {
     }
 }
 
+/// Whether the given device identifier is either in the range `0..count()` or the `WAVE_MAPPER`.
+///
+/// ```
+/// use winaudio::device;
+///
+/// assert!(device::is_valid_id(device::WAVE_MAPPER));
+/// assert!(!device::is_valid_id(device::count()));
+/// ```
+pub fn is_valid_id(id: u32) -> bool {
+    id == WAVE_MAPPER || id < count()
+}
+
 /// Retrieves the capabilities of a given waveform-audio output device.
+///
+/// The index must be in the range `0..count()`, or be `WAVE_MAPPER` to query the capabilities
+/// of the mapper itself. Any other index results in `Error::BadDeviceId`, without calling into
+/// Windows.
+///
+/// ```
+/// use winaudio::{device, Error};
+///
+/// let past_the_end = device::get_capabilities(device::count());
+/// assert_eq!(past_the_end.unwrap_err(), Error::BadDeviceId);
+/// ```
 pub fn get_capabilities(index: u32) -> Result<Capabilities, Error> {
+    if !is_valid_id(index) {
+        return Err(Error::BadDeviceId);
+    }
+
     let mut caps = MaybeUninit::uninit();
     let result = unsafe {
         waveOutGetDevCapsW(
@@ -413,11 +634,137 @@ pub fn get_capabilities(index: u32) -> Result<Capabilities, Error> {
             mem::size_of::<WAVEOUTCAPSW>() as u32,
         )
     };
-    check_multimedia_error(result)?;
+    check_multimedia_error_ctx(result, "waveOutGetDevCapsW")?;
     let caps = unsafe { caps.assume_init() };
     Ok(Capabilities { caps })
 }
 
+/// Retrieves the capabilities of every waveform-audio output device, paired with its index.
+///
+/// Unlike iterating over `0..count()` and skipping failures, devices whose capabilities can't
+/// be queried are included with their error, so that they can be shown as unavailable.
+///
+/// ```
+/// use winaudio::device;
+///
+/// for (index, caps) in device::get_capabilities_all() {
+///     match caps {
+///         Ok(caps) => println!("{}: {}", index, caps.name()),
+///         Err(e) => println!("{}: unavailable ({})", index, e),
+///     }
+/// }
+/// ```
+pub fn get_capabilities_all() -> Vec<(u32, Result<Capabilities, Error>)> {
+    (0..count())
+        .map(|index| (index, get_capabilities(index)))
+        .collect()
+}
+
+/// Snapshot of the waveform-audio output devices present in the system and their
+/// capabilities, which stays the same until it's explicitly refreshed.
+///
+/// Devices may be added or removed at any time, so calling `count` and then `get_capabilities`
+/// for each device may see them change halfway through. A `DeviceList` is queried once and can
+/// be read any number of times, such as every frame of a user interface, without calling into
+/// Windows.
+///
+/// ```
+/// use winaudio::device::{self, DeviceList};
+///
+/// let mut devices = DeviceList::new();
+/// assert_eq!(devices.len(), device::count());
+/// for (index, caps) in devices.iter() {
+///     if let Ok(caps) = caps {
+///         println!("{}: {}", index, caps.name());
+///     }
+/// }
+/// devices.refresh();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceList {
+    devices: Vec<Result<Capabilities, Error>>,
+}
+
+impl DeviceList {
+    /// Query the devices present in the system right now.
+    pub fn new() -> Self {
+        let mut list = Self {
+            devices: Vec::new(),
+        };
+        list.refresh();
+        list
+    }
+
+    /// Query the devices again, replacing the previous snapshot. Devices that were added or
+    /// removed since may shift the index of the others.
+    pub fn refresh(&mut self) {
+        self.devices = get_capabilities_all()
+            .into_iter()
+            .map(|(_, caps)| caps)
+            .collect();
+    }
+
+    /// Number of devices when the list was last refreshed.
+    pub fn len(&self) -> u32 {
+        self.devices.len() as u32
+    }
+
+    /// Whether there were no devices when the list was last refreshed.
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// Capabilities of the device with the given index, or the error that occurred when
+    /// querying them. Returns `None` if the index is not in the range `0..len()`.
+    pub fn get(&self, index: u32) -> Option<&Result<Capabilities, Error>> {
+        self.devices.get(index as usize)
+    }
+
+    /// Iterate over the capabilities of every device, paired with its index, like
+    /// `get_capabilities_all`.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &Result<Capabilities, Error>)> {
+        (0..).zip(self.devices.iter())
+    }
+}
+
+impl Default for DeviceList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Asks the given waveform-audio output device whether it can play the format, without
+/// opening it. Unlike `Capabilities::supports_format`, this works for any format.
+///
+/// ```no_run
+/// use winaudio::device::{self, WAVE_MAPPER};
+/// use winaudio::wave::Format;
+///
+/// let float = Format::ieee_float(2, 48000);
+/// println!("{}", device::is_format_supported(WAVE_MAPPER, &float).unwrap());
+/// ```
+pub fn is_format_supported(device_id: u32, fmt: &wave::Format) -> Result<bool, Error> {
+    if !is_valid_id(device_id) {
+        return Err(Error::BadDeviceId);
+    }
+
+    let result = unsafe {
+        waveOutOpen(
+            ptr::null_mut(),
+            device_id,
+            &fmt.c_struct(),
+            0,
+            0,
+            WAVE_FORMAT_QUERY,
+        )
+    };
+    match check_multimedia_error_ctx(result, "waveOutOpen") {
+        Ok(()) => Ok(true),
+        Err(Error::BadFormat) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
 /// Retrieves the number of waveform-audio output devices present in the system.
 pub fn count() -> u32 {
     unsafe { waveOutGetNumDevs() }
@@ -1,5 +1,13 @@
+use crate::util::last_error_context;
+use std::fmt;
+use std::io;
+use winapi::um::mmeapi::waveOutGetErrorTextW;
 use winapi::um::mmsystem::*;
 
+/// Maximum length of the text returned by `waveOutGetErrorTextW`, including the null terminator
+/// (`MAXERRORLENGTH`).
+const MAX_ERROR_LENGTH: usize = 256;
+
 enum_with_try_from!(
 /// Errors that can occur when working with Windows' sound API.
 pub enum Error(u32) {
@@ -54,3 +62,144 @@ pub enum Error(u32) {
     /// The device is synchronous but the device was opened without using the `AllowSync` flag.
     Sync = WAVERR_SYNC,
 });
+
+impl Error {
+    /// Whether the error is caused by a temporary condition, such as the device being in use,
+    /// so that retrying the same operation later may succeed. Other errors, like
+    /// `Error::BadFormat` or `Error::NotSupported`, will keep failing the same way.
+    ///
+    /// ```
+    /// use winaudio::Error;
+    ///
+    /// assert!(Error::Allocated.is_transient());
+    /// assert!(!Error::BadFormat.is_transient());
+    /// ```
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::Allocated | Self::HandleBusy | Self::StillPlaying | Self::MoreData
+        )
+    }
+
+    /// Description of the error provided by Windows, in the language of the system.
+    fn system_text(&self) -> Option<String> {
+        let mut text = [0u16; MAX_ERROR_LENGTH];
+        let result =
+            unsafe { waveOutGetErrorTextW(self.raw(), text.as_mut_ptr(), text.len() as u32) };
+        if result != MMSYSERR_NOERROR {
+            return None;
+        }
+        let len = text.iter().position(|&c| c == 0).unwrap_or(text.len());
+        match String::from_utf16_lossy(&text[..len]).trim() {
+            "" => None,
+            text => Some(text.to_string()),
+        }
+    }
+
+    /// Description of the error written for this crate, used when Windows has none.
+    fn fallback_text(&self) -> &'static str {
+        match self {
+            Self::Error => "unspecified error",
+            Self::BadDeviceId => "device ID out of range",
+            Self::NotEnabled => "driver failed enable",
+            Self::Allocated => "device already allocated",
+            Self::InvalidHandle => "device handle is invalid",
+            Self::NoDriver => "no device driver present",
+            Self::NoMemory => "memory allocation error",
+            Self::NotSupported => "function isn't supported",
+            Self::BadErrorNumber => "error value out of range",
+            Self::InvalidFlag => "invalid flag passed",
+            Self::InvalidParam => "invalid parameter passed",
+            Self::HandleBusy => "handle being used",
+            Self::InvalidAlias => "specified alias not found",
+            Self::BadDatabase => "bad registry database",
+            Self::KeyNotFound => "registry key not found",
+            Self::ReadError => "registry read error",
+            Self::WriteError => "registry write error",
+            Self::DeleteError => "registry delete error",
+            Self::ValueNotFound => "registry value not found",
+            Self::NoDriverCallback => "driver does not call DriverCallback",
+            Self::MoreData => "more data to be returned",
+            Self::BadFormat => "attempted to open with an unsupported waveform-audio format",
+            Self::StillPlaying => "there are still buffers in the queue",
+            Self::Unprepared => "the data block pointed to by the parameter hasn't been prepared",
+            Self::Sync => {
+                "the device is synchronous but was opened without the `WAVE_ALLOWSYNC` flag"
+            }
+        }
+    }
+}
+
+/// The description of the error is the one provided by Windows (`waveOutGetErrorTextW`), so it
+/// may be localized, or a description in English if Windows doesn't provide one.
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.system_text() {
+            Some(text) => f.write_str(&text),
+            None => f.write_str(self.fallback_text()),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Errors from the device are converted into the closest `io::ErrorKind`, or `Other`.
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        let kind = match error {
+            Error::BadDeviceId | Error::NoDriver | Error::KeyNotFound | Error::ValueNotFound => {
+                io::ErrorKind::NotFound
+            }
+            Error::BadFormat => io::ErrorKind::InvalidData,
+            Error::InvalidParam | Error::InvalidFlag => io::ErrorKind::InvalidInput,
+            Error::Allocated | Error::HandleBusy | Error::StillPlaying => {
+                io::ErrorKind::ResourceBusy
+            }
+            Error::NoMemory => io::ErrorKind::OutOfMemory,
+            Error::NotSupported => io::ErrorKind::Unsupported,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+/// An `Error` along with the name of the Windows function that returned it, useful to find
+/// out where an error came from when logging.
+///
+/// ```no_run
+/// use winaudio::device::WAVE_MAPPER;
+/// use winaudio::wave::{Format, Out};
+/// use winaudio::ErrorContext;
+///
+/// if let Err(e) = Out::open(WAVE_MAPPER, &Format::pcm(2, 44100, 16)) {
+///     match ErrorContext::last() {
+///         Some(ctx) if ctx.error == e => eprintln!("{}", ctx),
+///         _ => eprintln!("{:?}", e),
+///     }
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// The error that occurred.
+    pub error: Error,
+    /// Name of the Windows function that failed.
+    pub function: &'static str,
+}
+
+impl ErrorContext {
+    /// The context of the last error returned by a Windows function in the current thread.
+    /// Errors detected by this crate itself, without calling into Windows, are not included.
+    ///
+    /// A few problems that are worked around are also recorded, even though nothing failed,
+    /// such as `Error::InvalidFlag` from `waveOutPrepareHeader` when the driver didn't mark a
+    /// buffer as prepared.
+    pub fn last() -> Option<Self> {
+        last_error_context()
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed: {:?}", self.function, self.error)
+    }
+}
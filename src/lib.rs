@@ -14,8 +14,9 @@
 //! ```
 //! 
 //! If you're missing a certain function from the [mmeapi.h header], feel free to open an issue
-//! or send a pull request to the project to add it. This initial version doesn't have methods to
-//! set the pitch or playback rate for example, but they can trivially be added if needed.
+//! or send a pull request to the project to add it. Most of the waveform-audio output functions
+//! are already available, including the ones to set the pitch and playback rate on devices that
+//! support them.
 //!
 //! [Windows Multimedia API]: https://docs.microsoft.com/en-us/windows/win32/api/_multimedia/
 //! [mmeapi.h header]: https://docs.microsoft.com/en-us/windows/win32/api/mmeapi/
@@ -24,6 +25,7 @@
 mod util;
 pub mod device;
 mod error;
+pub mod prelude;
 pub mod wave;
 
-pub use error::Error;
+pub use error::{Error, ErrorContext};
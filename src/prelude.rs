@@ -0,0 +1,17 @@
+//! Re-exports of the most commonly used items, so that a single import is enough to get started:
+//!
+//! ```no_run
+//! use winaudio::prelude::*;
+//!
+//! println!("There are {} device(s).", device::count());
+//!
+//! let fmt = Format::pcm(2, 44100, 16);
+//! let out = Out::open(WAVE_MAPPER, &fmt).unwrap();
+//! ```
+//!
+//! The functions in `device` are not re-exported individually, as their names are too generic,
+//! but the module itself is.
+pub use crate::device::{self, WAVE_MAPPER};
+pub use crate::wave::format::Tag;
+pub use crate::wave::{Buffer, Format, Out, Player};
+pub use crate::Error;
@@ -1,12 +1,14 @@
 //! Several utilities to make implementing this crate less cumbersome.
-use crate::Error;
+use crate::error::{Error, ErrorContext};
+use std::cell::Cell;
 use std::convert::TryFrom;
-use std::io::{self, Read};
-use std::sync::{Condvar, Mutex};
+use std::io::{self, Read, Write};
+use std::sync::{Condvar, Mutex, PoisonError};
 use winapi::um::mmsystem::MMSYSERR_NOERROR;
 
-/// Automatically implement `TryFrom<primitive>` for enumerations with `#[repr(primitive)]`.
-/// It also derives the traits that are most desirable for these types of enumerations.
+/// Automatically implement `TryFrom<primitive>` for enumerations with `#[repr(primitive)]`,
+/// along with a `raw` method to convert them back. It also derives the traits that are most
+/// desirable for these types of enumerations.
 macro_rules! enum_with_try_from {
     (
         $(#[$outer:meta])*
@@ -28,6 +30,13 @@ macro_rules! enum_with_try_from {
             ),*
         }
 
+        impl $name {
+            /// The raw value this variant represents.
+            $vis const fn raw(self) -> $ty {
+                self as $ty
+            }
+        }
+
         impl std::convert::TryFrom<$ty> for $name {
             type Error = $ty;
 
@@ -47,23 +56,110 @@ macro_rules! enum_with_try_from {
     };
 }
 
+/// Like `enum_with_try_from`, but for enumerations where unknown values are still meaningful.
+/// Instead of failing, values with no variant are converted into the given fallback variant,
+/// which holds the raw value, and so `From<primitive>` is implemented in both directions, along
+/// with the same `raw` method.
+macro_rules! enum_with_fallback {
+    (
+        $(#[$outer:meta])*
+        $vis:vis enum $name:ident($ty:ident, $fallback:ident) {
+            $(
+                $(#[$inner:ident $($args:tt)*])*
+                $variant:ident = $value:expr
+            ),*
+            $(,)?
+        }
+    ) => {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        $(#[$outer])*
+        $vis enum $name {
+            $(
+                $(#[$inner $($args)*])*
+                $variant,
+            )*
+            /// Any other value not known by this crate. Values that are known are never
+            /// converted into this variant.
+            $fallback($ty),
+        }
+
+        impl $name {
+            /// The raw value this variant represents.
+            $vis const fn raw(self) -> $ty {
+                match self {
+                    $(
+                        $name::$variant => $value,
+                    )*
+                    $name::$fallback(value) => value,
+                }
+            }
+        }
+
+        impl From<$ty> for $name {
+            #[allow(non_upper_case_globals)]
+            fn from(value: $ty) -> Self {
+                $(
+                    const $variant: $ty = $value;
+                )*
+                match value {
+                    $(
+                        $variant => $name::$variant,
+                    )*
+                    _ => $name::$fallback(value),
+                }
+            }
+        }
+
+        impl From<$name> for $ty {
+            fn from(value: $name) -> Self {
+                value.raw()
+            }
+        }
+    };
+}
+
 /// Helper trait to read little-endian integers from binary data.
 pub(crate) trait BinaryRead: Read {
     fn read_u16(&mut self) -> io::Result<u16> {
         let mut buffer = [0; 2];
-        self.read(&mut buffer)?;
+        self.read_exact(&mut buffer)?;
         Ok(u16::from_le_bytes(buffer))
     }
 
     fn read_u32(&mut self) -> io::Result<u32> {
         let mut buffer = [0; 4];
-        self.read(&mut buffer)?;
+        self.read_exact(&mut buffer)?;
         Ok(u32::from_le_bytes(buffer))
     }
 }
 
 impl<T> BinaryRead for T where T: Read {}
 
+/// Helper trait to write little-endian integers as binary data.
+pub(crate) trait BinaryWrite: Write {
+    fn write_u16(&mut self, value: u16) -> io::Result<()> {
+        self.write_all(&value.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, value: u32) -> io::Result<()> {
+        self.write_all(&value.to_le_bytes())
+    }
+}
+
+impl<T> BinaryWrite for T where T: Write {}
+
+/// Read as many bytes as possible into the buffer, until it's full or the end is reached.
+pub(crate) fn read_chunk<R: Read>(stream: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match stream.read(&mut buffer[len..])? {
+            0 => break,
+            read => len += read,
+        }
+    }
+    Ok(len)
+}
+
 /// Convert the error code into the proper `Error` variant.
 pub(crate) fn check_multimedia_error(result: u32) -> Result<(), Error> {
     if result == MMSYSERR_NOERROR {
@@ -73,7 +169,35 @@ pub(crate) fn check_multimedia_error(result: u32) -> Result<(), Error> {
     }
 }
 
+thread_local! {
+    static LAST_ERROR: Cell<Option<ErrorContext>> = const { Cell::new(None) };
+}
+
+/// Like `check_multimedia_error`, but also remember the name of the function that failed,
+/// so that it can be retrieved with `ErrorContext::last`.
+pub(crate) fn check_multimedia_error_ctx(result: u32, function: &'static str) -> Result<(), Error> {
+    let result = check_multimedia_error(result);
+    if let Err(error) = result {
+        record_error_context(error, function);
+    }
+    result
+}
+
+/// Remember a problem with the result of a Windows function even though it didn't fail, so
+/// that it can be retrieved with `ErrorContext::last`.
+pub(crate) fn record_error_context(error: Error, function: &'static str) {
+    LAST_ERROR.with(|last| last.set(Some(ErrorContext { error, function })));
+}
+
+/// The context of the last error that occurred in this thread, if any.
+pub(crate) fn last_error_context() -> Option<ErrorContext> {
+    LAST_ERROR.with(|last| last.get())
+}
+
 /// Helper struct to store an event flag and the condition variable to wait on it.
+///
+/// The flag is valid no matter where a panic happened, so a poisoned lock is used as-is. This
+/// matters because the event is set from the device callback, which must never panic.
 pub(crate) struct Event {
     mutex: Mutex<bool>,
     cond: Condvar,
@@ -90,22 +214,25 @@ impl Event {
 
     /// Set the event. This will wake up everyone `wait`ing on it.
     pub(crate) fn set(&self) {
-        let mut guard = self.mutex.lock().unwrap();
+        let mut guard = self.mutex.lock().unwrap_or_else(PoisonError::into_inner);
         *guard = true;
         self.cond.notify_all();
     }
 
     /// Clear the event. Calls to `wait` will block until it's `set`.
     pub(crate) fn clear(&self) {
-        let mut guard = self.mutex.lock().unwrap();
+        let mut guard = self.mutex.lock().unwrap_or_else(PoisonError::into_inner);
         *guard = false;
     }
 
     /// Wait for the event to be `set`.
     pub(crate) fn wait(&self) {
-        let mut guard = self.mutex.lock().unwrap();
+        let mut guard = self.mutex.lock().unwrap_or_else(PoisonError::into_inner);
         while !*guard {
-            guard = self.cond.wait(guard).unwrap();
+            guard = self
+                .cond
+                .wait(guard)
+                .unwrap_or_else(PoisonError::into_inner);
         }
     }
 }
@@ -0,0 +1,65 @@
+//! Metadata stored in the `bext` chunk of Broadcast Wave Format files.
+use crate::util::BinaryRead as _;
+use std::io::{self, Read};
+
+/// Contents of the `bext` chunk of a Broadcast Wave Format file, as defined by EBU Tech 3285.
+///
+/// Text fields are stored as fixed-size ASCII strings in the file, and any padding is removed.
+/// Only the fields present since the first version of the chunk are read.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BroadcastExtension {
+    /// Free description of the sound sequence.
+    pub description: String,
+    /// Name of the originator or producer of the file.
+    pub originator: String,
+    /// Unique identifier of the file given by the originator.
+    pub originator_reference: String,
+    /// Date of creation of the sound sequence, in the form `yyyy-mm-dd`.
+    pub origination_date: String,
+    /// Time of creation of the sound sequence, in the form `hh-mm-ss`.
+    pub origination_time: String,
+    /// Sample count since midnight of the first sample of the sound sequence.
+    pub time_reference: u64,
+    /// Version of the chunk.
+    pub version: u16,
+}
+
+impl BroadcastExtension {
+    /// Length of the fields that are read from the start of the chunk.
+    pub(crate) const LEN: u64 = 256 + 32 + 32 + 10 + 8 + 8 + 2;
+
+    /// Read the fields from the contents of a `bext` chunk, which the reader must be positioned
+    /// at. The integer fields of big-endian `RIFX` files are byte-swapped.
+    pub(crate) fn read_from<R: Read>(reader: &mut R, big_endian: bool) -> io::Result<Self> {
+        let mut raw = [0; Self::LEN as usize];
+        reader.read_exact(&mut raw)?;
+
+        if big_endian {
+            // Reverse each of the integer fields so that they can be read as little-endian.
+            raw[338..342].reverse();
+            raw[342..346].reverse();
+            raw[346..348].reverse();
+        }
+        let mut ints = &raw[338..];
+        let low = ints.read_u32()? as u64;
+        let high = ints.read_u32()? as u64;
+        let version = ints.read_u16()?;
+
+        Ok(Self {
+            description: text(&raw[0..256]),
+            originator: text(&raw[256..288]),
+            originator_reference: text(&raw[288..320]),
+            origination_date: text(&raw[320..330]),
+            origination_time: text(&raw[330..338]),
+            time_reference: (high << 32) | low,
+            version,
+        })
+    }
+}
+
+/// Text stored in a fixed-size field, which is padded with NUL bytes (or sometimes spaces).
+fn text(raw: &[u8]) -> String {
+    let end = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+    String::from_utf8_lossy(&raw[..end]).trim_end().to_string()
+}
@@ -1,5 +1,11 @@
+use crate::util::read_chunk;
+use crate::Error;
 use std::io::{self, Read};
+use std::iter;
+use std::ptr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use winapi::um::mmsystem::WAVEHDR;
+use winapi::um::winnt::LPSTR;
 
 /// Prepared buffer (header and data) that can be sent to an output device.
 pub struct Buffer {
@@ -7,13 +13,208 @@ pub struct Buffer {
     pub(crate) buffer: Box<[u8]>,
 }
 
+/// Memory for the buffers of `Out`, kept around once a device is closed so that the next one
+/// opened with `Out::open_with_pool` doesn't need to allocate it again. This helps when opening
+/// many short-lived devices, such as one per sound effect.
+///
+/// The memory is still prepared again for every device, as preparing binds it to the handle of
+/// the device. Cloning the pool gives another handle to the same memory, so it can be shared
+/// by devices opened from different threads.
+///
+/// ```no_run
+/// use winaudio::device::WAVE_MAPPER;
+/// use winaudio::wave::{BufferPool, Format, Out};
+///
+/// let pool = BufferPool::new();
+/// let fmt = Format::pcm(2, 44100, 16);
+/// for _ in 0..10 {
+///     let mut out = Out::open_with_pool(&pool, WAVE_MAPPER, &fmt).unwrap();
+///     out.play_slice(&[0; 4410]).unwrap();
+/// }
+/// assert_eq!(pool.len(), 2);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct BufferPool {
+    free: Arc<Mutex<Vec<Box<[u8]>>>>,
+}
+
+impl BufferPool {
+    /// Create a new pool, which holds no memory until the first device using it is closed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of allocations that are currently free to be reused.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Whether there is no memory free to be reused.
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Free all of the memory held by the pool. Buffers in use by devices are not affected,
+    /// and are given back to the pool once the devices are closed.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Take an allocation of exactly the given size out of the pool, zeroed, if there is one.
+    pub(crate) fn take(&self, size: usize) -> Option<Box<[u8]>> {
+        let mut free = self.lock();
+        let index = free.iter().position(|buffer| buffer.len() == size)?;
+        let mut buffer = free.swap_remove(index);
+        buffer.iter_mut().for_each(|x| *x = 0);
+        Some(buffer)
+    }
+
+    /// Give the memory of a buffer that is no longer prepared back to the pool.
+    pub(crate) fn put(&self, buffer: Box<[u8]>) {
+        if !buffer.is_empty() {
+            self.lock().push(buffer);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Box<[u8]>>> {
+        self.free.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// What happened when reading data into a `Buffer`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadOutcome {
+    /// How many bytes were read, which is the valid length of the buffer.
+    pub bytes: usize,
+    /// Whether not all of the buffer was filled, meaning that the end of the stream has been
+    /// reached and no more data can be read.
+    pub eof: bool,
+    /// Whether the bytes read are a whole number of blocks. If not, the stream ended in the
+    /// middle of a block, and the valid length may need to be padded up to the next one.
+    pub frame_aligned: bool,
+}
+
 impl Buffer {
-    /// Reads the next chunk of data into the memory buffer. Returns `false` if not all data was
-    /// filled, meaning that the end of the stream has been reached and no more data can be read.
-    pub fn read<R: Read>(&mut self, stream: &mut R) -> io::Result<bool> {
-        let read = stream.read(&mut self.buffer)?;
+    /// Wrap the memory in a buffer whose valid length is all of it. It still has to be prepared
+    /// before it can be sent to a device.
+    pub(crate) fn new(mut buffer: Box<[u8]>) -> Self {
+        let header = WAVEHDR {
+            lpData: buffer.as_mut_ptr() as LPSTR,
+            dwBufferLength: buffer.len() as u32,
+            dwBytesRecorded: 0,
+            dwUser: 0,
+            dwFlags: 0,
+            dwLoops: 0,
+            lpNext: ptr::null_mut(),
+            reserved: 0,
+        };
+        Self { header, buffer }
+    }
+
+    /// Reads the next chunk of data into the memory buffer, which is made of blocks of
+    /// `block_align` bytes. The rest of the buffer after the data read is zeroed.
+    pub fn read<R: Read>(&mut self, stream: &mut R, block_align: u16) -> io::Result<ReadOutcome> {
+        // A single read may return less than requested before the end, and if that happened
+        // to split a block (such as a 4-byte float sample), the rest would be misaligned.
+        let read = read_chunk(stream, &mut self.buffer)?;
         self.buffer[read..].iter_mut().for_each(|x| *x = 0);
         self.header.dwBufferLength = read as u32;
-        Ok(read == self.buffer.len())
+        Ok(ReadOutcome {
+            bytes: read,
+            eof: read < self.buffer.len(),
+            frame_aligned: read % block_align.max(1) as usize == 0,
+        })
+    }
+
+    /// The data that will be sent to the device, up to the valid length.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buffer[..self.header.dwBufferLength as usize]
+    }
+
+    /// The whole memory buffer, so that it can be filled directly. After writing to it,
+    /// `set_valid_len` must be used to indicate how many bytes should be sent to the device.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.buffer
     }
+
+    /// Size of the memory buffer, which is the most data that can be sent to the device at once.
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Zero the whole memory buffer and set the valid length to zero, so that no data from a
+    /// previous use can be sent to the device again.
+    ///
+    /// ```
+    /// use winaudio::device::{self, WAVE_MAPPER};
+    /// use winaudio::wave::{Format, Out};
+    ///
+    /// # // Skip instead of failing where there are no devices at all, not even a null one.
+    /// # if device::count() == 0 {
+    /// #     return;
+    /// # }
+    /// let mut out = Out::open(WAVE_MAPPER, &Format::pcm(1, 8000, 8)).unwrap();
+    /// let buffer = out.buffer_mut(0).unwrap();
+    /// buffer.as_mut_slice().iter_mut().for_each(|x| *x = 1);
+    /// buffer.set_valid_len(buffer.capacity()).unwrap();
+    ///
+    /// buffer.clear();
+    /// assert!(buffer.as_slice().is_empty());
+    ///
+    /// // Refilling only part of it leaves nothing from before in the rest.
+    /// buffer.as_mut_slice()[..4].copy_from_slice(&[2; 4]);
+    /// buffer.set_valid_len(4).unwrap();
+    /// assert_eq!(buffer.as_slice(), &[2; 4]);
+    /// assert!(buffer.as_mut_slice()[4..].iter().all(|&x| x == 0));
+    /// ```
+    pub fn clear(&mut self) {
+        self.buffer.iter_mut().for_each(|x| *x = 0);
+        self.header.dwBufferLength = 0;
+    }
+
+    /// Set how many bytes from the start of the buffer should be sent to the device.
+    /// Returns `Error::InvalidParam` if the length exceeds the size of the buffer.
+    pub fn set_valid_len(&mut self, len: usize) -> Result<(), Error> {
+        if len > self.buffer.len() {
+            return Err(Error::InvalidParam);
+        }
+        self.header.dwBufferLength = len as u32;
+        Ok(())
+    }
+}
+
+/// Split the data into the chunks that are written to each buffer of a device, in order, when
+/// every buffer holds `capacity` bytes and the next one already has `pending` bytes in it. The
+/// first chunk fills what's left of that buffer, and the rest fill whole buffers, except for the
+/// last one, so no data is ever left out.
+///
+/// If the next buffer is already full, the first chunk is empty, as that buffer is written on
+/// its own before the data.
+///
+/// ```
+/// use winaudio::wave::split_into_buffers;
+///
+/// let data = (0..4 * 256).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+/// let chunks = split_into_buffers(&data, 0, 256).collect::<Vec<_>>();
+/// assert_eq!(chunks.iter().map(|c| c.len()).collect::<Vec<_>>(), vec![256; 4]);
+/// assert_eq!(chunks.concat(), data);
+///
+/// let lengths = split_into_buffers(&data[..600], 100, 256).map(|c| c.len());
+/// assert_eq!(lengths.collect::<Vec<_>>(), vec![156, 256, 188]);
+/// ```
+pub fn split_into_buffers(
+    mut data: &[u8],
+    pending: usize,
+    capacity: usize,
+) -> impl Iterator<Item = &[u8]> {
+    let mut room = capacity.saturating_sub(pending);
+    iter::from_fn(move || {
+        if data.is_empty() {
+            return None;
+        }
+        let (chunk, rest) = data.split_at(room.min(data.len()));
+        data = rest;
+        room = capacity;
+        Some(chunk)
+    })
 }
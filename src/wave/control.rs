@@ -0,0 +1,81 @@
+//! Control over a playback running on another thread.
+use crate::wave::Player;
+use std::io;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Commands sent from a `PlaybackHandle` to the thread playing a `Player`, which are applied
+/// between the chunks of data written to the device.
+pub(crate) enum Command {
+    SetVolume(f32, f32),
+    Pause,
+    Resume,
+    Seek(Duration),
+    Stop,
+}
+
+/// Handle to a `Player` playing on its own thread, created with `Player::spawn`.
+///
+/// Each method sends a command to the playback thread and returns right away, without waiting
+/// for it to be applied. Commands sent after the playback finished have no effect, and any error
+/// that happens while applying them ends the playback, to be returned by `join`.
+///
+/// Dropping the handle lets the playback continue until the end, unless it was paused, in which
+/// case it ends right away as there would be no way to resume it.
+pub struct PlaybackHandle {
+    commands: Sender<Command>,
+    thread: JoinHandle<io::Result<Player>>,
+}
+
+impl PlaybackHandle {
+    pub(crate) fn spawn(mut player: Player) -> Self {
+        let (commands, receiver) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            player.play_commands(&receiver)?;
+            Ok(player)
+        });
+        Self { commands, thread }
+    }
+
+    /// Set the volume of the device. A value of 1.0 represents full volume, and 0.0 silence.
+    pub fn set_volume(&self, left: f32, right: f32) {
+        self.send(Command::SetVolume(left, right));
+    }
+
+    /// Pause the playback until `resume` is called.
+    pub fn pause(&self) {
+        self.send(Command::Pause);
+    }
+
+    /// Resume the playback after a `pause`.
+    pub fn resume(&self) {
+        self.send(Command::Resume);
+    }
+
+    /// Continue playing from the given position, discarding the data that was already written.
+    /// This is only supported for `.wav` files that were not read with `Player::from_unseekable`,
+    /// and positions past the end finish the playback.
+    pub fn seek(&self, position: Duration) {
+        self.send(Command::Seek(position));
+    }
+
+    /// Stop the playback, discarding the data that was already written.
+    pub fn stop(&self) {
+        self.send(Command::Stop);
+    }
+
+    /// Wait for the playback to end, and get the player back so that it can be played again.
+    /// As this drops the handle, a paused playback ends right away.
+    pub fn join(self) -> io::Result<Player> {
+        drop(self.commands);
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("playback panicked")))
+    }
+
+    fn send(&self, command: Command) {
+        // The playback may have finished already, in which case there's nothing to control.
+        let _ = self.commands.send(command);
+    }
+}
@@ -0,0 +1,99 @@
+//! Conversion between the sample types used by PCM data, and of the other values used by the
+//! device.
+//!
+//! 8-bit PCM samples are unsigned, with silence at 128, while 16-bit samples are signed, with
+//! silence at 0.
+
+/// Convert unsigned 8-bit samples to signed 16-bit samples.
+///
+/// ```
+/// use winaudio::wave::convert::u8_to_i16;
+///
+/// assert_eq!(u8_to_i16(&[0, 128, 255]), vec![-32768, 0, 32512]);
+/// ```
+pub fn u8_to_i16(samples: &[u8]) -> Vec<i16> {
+    samples.iter().map(|&s| ((s as i16) - 128) << 8).collect()
+}
+
+/// Convert signed 16-bit samples to unsigned 8-bit samples, dropping the lowest 8 bits.
+///
+/// ```
+/// use winaudio::wave::convert::i16_to_u8;
+///
+/// assert_eq!(i16_to_u8(&[-32768, 0, 255, 32767]), vec![0, 128, 128, 255]);
+/// ```
+pub fn i16_to_u8(samples: &[i16]) -> Vec<u8> {
+    samples.iter().map(|&s| ((s >> 8) + 128) as u8).collect()
+}
+
+/// Convert floating point samples in the `-1.0..=1.0` range to signed 16-bit samples. Samples
+/// out of range are clipped, and both ends map to the same magnitude.
+///
+/// ```
+/// use winaudio::wave::convert::f32_to_i16;
+///
+/// assert_eq!(f32_to_i16(&[0.0, 1.0, -1.0]), vec![0, i16::MAX, -i16::MAX]);
+/// assert_eq!(f32_to_i16(&[1.5, -1.5]), vec![i16::MAX, -i16::MAX]);
+/// ```
+pub fn f32_to_i16(samples: &[f32]) -> Vec<i16> {
+    samples
+        .iter()
+        .map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+        .collect()
+}
+
+/// Convert a multiplier, such as the pitch or playback rate of a device, to the fixed-point
+/// 16.16 value the device uses, where the high word is the integer part and the low word the
+/// fractional part. Values that don't fit are clamped to `0.0..65536.0`.
+///
+/// ```
+/// use winaudio::wave::convert::to_fixed_16_16;
+///
+/// assert_eq!(to_fixed_16_16(1.0), 0x0001_0000);
+/// assert_eq!(to_fixed_16_16(1.5), 0x0001_8000);
+/// ```
+pub fn to_fixed_16_16(value: f32) -> u32 {
+    (value as f64 * 65536.0).round().clamp(0.0, u32::MAX as f64) as u32
+}
+
+/// Convert a fixed-point 16.16 value used by the device back to a multiplier.
+///
+/// ```
+/// use winaudio::wave::convert::from_fixed_16_16;
+///
+/// assert_eq!(from_fixed_16_16(0x0000_8000), 0.5);
+/// ```
+pub fn from_fixed_16_16(raw: u32) -> f32 {
+    (raw as f64 / 65536.0) as f32
+}
+
+/// Convert a volume in the `0.0..=1.0` range to the 16-bit value used by the device for each
+/// channel, rounding to the nearest step so that 1.0 is always full volume. Values out of range
+/// are clamped.
+///
+/// ```
+/// use winaudio::wave::convert::{raw_to_volume, volume_to_raw};
+///
+/// assert_eq!(volume_to_raw(0.0), 0);
+/// assert_eq!(volume_to_raw(1.0), 0xFFFF);
+///
+/// // Converting back and forth is off by at most one step.
+/// for i in 0..=1000 {
+///     let volume = i as f32 / 1000.0;
+///     assert!((raw_to_volume(volume_to_raw(volume)) - volume).abs() <= 1.0 / 65535.0);
+/// }
+/// ```
+pub fn volume_to_raw(volume: f32) -> u32 {
+    (volume * 65535.0).round().clamp(0.0, 65535.0) as u32
+}
+
+/// Convert a 16-bit volume used by the device back to the `0.0..=1.0` range.
+///
+/// ```
+/// use winaudio::wave::convert::raw_to_volume;
+///
+/// assert_eq!(raw_to_volume(0xFFFF), 1.0);
+/// ```
+pub fn raw_to_volume(raw: u32) -> f32 {
+    raw as f32 / 65535.0
+}
@@ -0,0 +1,65 @@
+use crate::wave::format::Tag;
+use crate::wave::Format;
+use crate::Error;
+use cpal::{BufferSize, SampleFormat, SampleRate, StreamConfig};
+use std::convert::TryFrom;
+
+impl Format {
+    /// Describe the same interleaved data as a `cpal` stream configuration and sample format.
+    ///
+    /// Only the sample formats that the output device can play directly are supported, which
+    /// are unsigned 8-bit, signed 16-bit and 32-bit, and floating point. Any other sample format
+    /// results in `Error::BadFormat`.
+    pub fn from_cpal(config: &StreamConfig, sample_format: SampleFormat) -> Result<Self, Error> {
+        let (tag, bits) = match sample_format {
+            SampleFormat::U8 => (Tag::Pcm, 8),
+            SampleFormat::I16 => (Tag::Pcm, 16),
+            SampleFormat::I32 => (Tag::Pcm, 32),
+            SampleFormat::F32 => (Tag::IeeeFloat, 32),
+            SampleFormat::F64 => (Tag::IeeeFloat, 64),
+            _ => return Err(Error::BadFormat),
+        };
+        Ok(Self::with_tag(
+            tag,
+            config.channels,
+            config.sample_rate.0,
+            bits,
+        ))
+    }
+
+    /// Describe the format as a `cpal` stream configuration, with the default buffer size, and
+    /// sample format. Returns `Error::BadFormat` if `cpal` has no equivalent sample format.
+    pub fn to_cpal(&self) -> Result<(StreamConfig, SampleFormat), Error> {
+        let sample_format = match (self.format_tag, self.bits_per_sample) {
+            (Tag::Pcm, 8) => SampleFormat::U8,
+            (Tag::Pcm, 16) => SampleFormat::I16,
+            (Tag::Pcm, 32) => SampleFormat::I32,
+            (Tag::IeeeFloat, 32) => SampleFormat::F32,
+            (Tag::IeeeFloat, 64) => SampleFormat::F64,
+            _ => return Err(Error::BadFormat),
+        };
+
+        let config = StreamConfig {
+            channels: self.channels,
+            sample_rate: SampleRate(self.samples_per_sec),
+            buffer_size: BufferSize::Default,
+        };
+        Ok((config, sample_format))
+    }
+}
+
+impl TryFrom<(StreamConfig, SampleFormat)> for Format {
+    type Error = Error;
+
+    fn try_from((config, sample_format): (StreamConfig, SampleFormat)) -> Result<Self, Error> {
+        Self::from_cpal(&config, sample_format)
+    }
+}
+
+impl TryFrom<Format> for (StreamConfig, SampleFormat) {
+    type Error = Error;
+
+    fn try_from(fmt: Format) -> Result<Self, Error> {
+        fmt.to_cpal()
+    }
+}
@@ -0,0 +1,187 @@
+use crate::wave::Format;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{self, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Decodes audio files which can't be played directly by the output device, such as MP3, FLAC
+/// or Ogg Vorbis, into interleaved 16-bit PCM data, which can be read on the fly.
+///
+/// Only the default audio track of the file is decoded.
+pub struct Decoder {
+    reader: Box<dyn FormatReader>,
+    decoder: Box<dyn codecs::Decoder>,
+    track_id: u32,
+    fmt: Format,
+    frame_count: Option<u64>,
+    // Bytes from the last decoded packet, and how many of them have been read so far.
+    decoded: Vec<u8>,
+    pos: usize,
+}
+
+impl Decoder {
+    /// Probe the contents of the file in the reader and prepare to decode its default audio track.
+    pub fn new<R: Read + Seek + Send + 'static>(mut reader: R) -> io::Result<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        let source = Source {
+            reader: Mutex::new(reader),
+            len,
+        };
+        let stream = MediaSourceStream::new(Box::new(source), Default::default());
+        let reader = symphonia::default::get_probe()
+            .format(
+                &Hint::new(),
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .map_err(to_io_error)?
+            .format;
+
+        let track = reader
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing audio track"))?;
+
+        let params = &track.codec_params;
+        let channels = params
+            .channels
+            .map(|c| c.count() as u16)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown channel count"))?;
+        let sample_rate = params
+            .sample_rate
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown sample rate"))?;
+
+        let decoder = symphonia::default::get_codecs()
+            .make(params, &DecoderOptions::default())
+            .map_err(to_io_error)?;
+
+        Ok(Self {
+            track_id: track.id,
+            fmt: Format::pcm(channels, sample_rate, 16),
+            frame_count: params.n_frames,
+            reader,
+            decoder,
+            decoded: Vec::new(),
+            pos: 0,
+        })
+    }
+
+    /// Format of the decoded data, which is always 16-bit PCM.
+    pub fn format(&self) -> Format {
+        self.fmt
+    }
+
+    /// Total number of frames in the track, if the file says so.
+    pub fn frame_count(&self) -> Option<u64> {
+        self.frame_count
+    }
+
+    /// Go back to the beginning of the track, so that it can be decoded again.
+    pub fn rewind(&mut self) -> io::Result<()> {
+        self.reader
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::TimeStamp {
+                    ts: 0,
+                    track_id: self.track_id,
+                },
+            )
+            .map_err(to_io_error)?;
+        self.decoder.reset();
+        self.decoded.clear();
+        self.pos = 0;
+        Ok(())
+    }
+
+    /// Decode the next packet of the track. Returns `false` if there are no more packets.
+    fn decode_next(&mut self) -> io::Result<bool> {
+        loop {
+            let packet = match self.reader.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(false)
+                }
+                Err(e) => return Err(to_io_error(e)),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let audio = match self.decoder.decode(&packet) {
+                Ok(audio) => audio,
+                // A malformed packet only causes a short gap, so keep going.
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(e) => return Err(to_io_error(e)),
+            };
+
+            let mut samples = SampleBuffer::<i16>::new(audio.capacity() as u64, *audio.spec());
+            samples.copy_interleaved_ref(audio);
+            self.decoded.clear();
+            self.decoded
+                .extend(samples.samples().iter().flat_map(|s| s.to_le_bytes()));
+            self.pos = 0;
+            return Ok(true);
+        }
+    }
+}
+
+impl Read for Decoder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.decoded.len() {
+            if !self.decode_next()? {
+                return Ok(0);
+            }
+        }
+
+        let len = buf.len().min(self.decoded.len() - self.pos);
+        buf[..len].copy_from_slice(&self.decoded[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// Adapts any seekable reader so that it can be used as a media source.
+struct Source<R> {
+    // The media source must be `Sync`, but it's only ever used through `&mut`, so the mutex
+    // is never actually locked.
+    reader: Mutex<R>,
+    len: u64,
+}
+
+impl<R: Read> Read for Source<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.get_mut().unwrap().read(buf)
+    }
+}
+
+impl<R: Seek> Seek for Source<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.reader.get_mut().unwrap().seek(pos)
+    }
+}
+
+impl<R: Read + Seek + Send> MediaSource for Source<R> {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.len)
+    }
+}
+
+fn to_io_error(error: SymphoniaError) -> io::Error {
+    match error {
+        SymphoniaError::IoError(e) => e,
+        SymphoniaError::Unsupported(_) => io::Error::new(io::ErrorKind::Unsupported, error),
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
@@ -1,12 +1,21 @@
 //! Information related to the format of waveform-audio data.
 use crate::util::BinaryRead as _;
-use std::convert::TryInto;
+use std::convert::TryFrom;
+use std::fmt;
 use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::BitOr;
+use std::time::Duration;
+use winapi::shared::guiddef::GUID;
 use winapi::shared::mmreg::*;
 
-enum_with_try_from!(
+enum_with_fallback!(
 /// Waveform-audio format type.
-pub enum Tag(u16) {
+///
+/// Format types added after this enumeration was written are represented by `Tag::Other`,
+/// so that files using them can still be parsed. Whether they can be played back is up to
+/// the output device.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Tag(u16, Other) {
     /// Format used for For one- or two-channel PCM data.
     Pcm = WAVE_FORMAT_PCM,
     /// Microsoft Corporation.
@@ -541,8 +550,354 @@ pub enum Tag(u16) {
     Flac = WAVE_FORMAT_FLAC,
 });
 
+impl Tag {
+    /// Resolve the `SubFormat` of a `WAVE_FORMAT_EXTENSIBLE` format into the tag of the actual
+    /// format, such as `Tag::Pcm` for `KSDATAFORMAT_SUBTYPE_PCM`.
+    ///
+    /// The media subtype GUIDs of formats with a tag are the base GUID
+    /// `XXXXXXXX-0000-0010-8000-00AA00389B71` with the tag in place of the `X`. Returns `None`
+    /// for any other GUID.
+    ///
+    /// ```
+    /// use winapi::shared::guiddef::GUID;
+    /// use winaudio::wave::format::Tag;
+    ///
+    /// let ieee_float = GUID {
+    ///     Data1: 0x0003,
+    ///     Data2: 0x0000,
+    ///     Data3: 0x0010,
+    ///     Data4: [0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71],
+    /// };
+    /// assert_eq!(Tag::from_subformat_guid(&ieee_float), Some(Tag::IeeeFloat));
+    /// ```
+    pub fn from_subformat_guid(guid: &GUID) -> Option<Self> {
+        const BASE_DATA4: [u8; 8] = [0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71];
+        if guid.Data2 != 0x0000 || guid.Data3 != 0x0010 || guid.Data4 != BASE_DATA4 {
+            return None;
+        }
+        u16::try_from(guid.Data1).ok().map(Self::from)
+    }
+
+    /// The family of codecs the format belongs to, to group the many format types into a few
+    /// categories. Format types that don't fit in any of them, including `Tag::Other`, are in
+    /// `Family::Other`.
+    ///
+    /// ```
+    /// use winaudio::wave::format::{Family, Tag};
+    ///
+    /// assert_eq!(Tag::Pcm.family(), Family::Pcm);
+    /// assert_eq!(Tag::MpegLayer3.family(), Family::Mpeg);
+    /// assert_eq!(Tag::Flac.family(), Family::Lossless);
+    /// assert_eq!(Tag::Other(0xfffe).family(), Family::Other);
+    /// ```
+    pub fn family(&self) -> Family {
+        match self {
+            Self::Pcm
+            | Self::IeeeFloat
+            | Self::Alaw
+            | Self::Mulaw
+            | Self::EsPcm
+            | Self::UnisysNapUlaw
+            | Self::UnisysNapAlaw
+            | Self::VmeVmpcm => Family::Pcm,
+            Self::AdPcm
+            | Self::OkiAdPcm
+            | Self::DviImaAdPcm
+            | Self::MediaspaceAdPcm
+            | Self::SierraAdPcm
+            | Self::G723AdPcm
+            | Self::DialogicOkiAdPcm
+            | Self::MediavisionAdPcm
+            | Self::YamahaAdPcm
+            | Self::AntexAdpcme
+            | Self::DigiAdPcm
+            | Self::NmsVbxAdPcm
+            | Self::CsImaAdPcm
+            | Self::RockwellAdPcm
+            | Self::G721AdPcm
+            | Self::SharpG726
+            | Self::G726AdPcm
+            | Self::G722AdPcm
+            | Self::DfG726
+            | Self::InfocomItsG721AdPcm
+            | Self::ZyxelAdPcm
+            | Self::RhetorexAdPcm
+            | Self::SanyoLdAdPcm
+            | Self::G726Adpcm
+            | Self::UnisysNapAdPcm
+            | Self::SycomAcmSyc701G726L
+            | Self::KnowledgeAdventureAdPcm
+            | Self::CreativeAdPcm
+            | Self::UherAdPcm
+            | Self::NorcomVoiceSystemsAdPcm
+            | Self::Oliadpcm
+            | Self::InningsTelecomAdPcm
+            | Self::DivioG726
+            | Self::IngenientG726
+            | Self::EncoreG726
+            | Self::PolycomG722
+            | Self::NiceAdPcm
+            | Self::VocordG721
+            | Self::VocordG726 => Family::Adpcm,
+            Self::Mpeg | Self::MpegLayer3 => Family::Mpeg,
+            Self::NecAac
+            | Self::RawAac1
+            | Self::FraunhoferIisMpeg2Aac
+            | Self::MpegAdtsAac
+            | Self::MpegRawAac
+            | Self::MpegLoas
+            | Self::NokiaMpegAdtsAac
+            | Self::NokiaMpegRawAac
+            | Self::VodafoneMpegAdtsAac
+            | Self::VodafoneMpegRawAac
+            | Self::MpegHeaac
+            | Self::DivioMpeg4Aac
+            | Self::FaadAac
+            | Self::Mpeg4Aac => Family::Aac,
+            Self::MsGsm610
+            | Self::DfGsm610
+            | Self::RacalRecorderGsm
+            | Self::Tubgsm
+            | Self::Oligsm
+            | Self::Gsm610
+            | Self::Gsm620
+            | Self::Gsm660
+            | Self::Gsm690 => Family::Gsm,
+            Self::Vselp
+            | Self::IbmCvsd
+            | Self::WmaVoice9
+            | Self::WmaVoice10
+            | Self::HpDynVoice
+            | Self::DspGroupTrueSpeech
+            | Self::Prosody1612
+            | Self::Lrc
+            | Self::ControlResVqlpc
+            | Self::G728Celp
+            | Self::Msg723
+            | Self::IntelG7231
+            | Self::IntelG729
+            | Self::Rt24
+            | Self::LucentG723
+            | Self::Voxware
+            | Self::VoxwareByteAligned
+            | Self::VoxwareAc8
+            | Self::VoxwareAc10
+            | Self::VoxwareAc16
+            | Self::VoxwareAc20
+            | Self::VoxwareRt24
+            | Self::VoxwareRt29
+            | Self::VoxwareRt29HW
+            | Self::VoxwareVr12
+            | Self::VoxwareVr18
+            | Self::VoxwareTq40
+            | Self::VoxwareSc3
+            | Self::VoxwareSc31
+            | Self::VoxwareTq60
+            | Self::Msrt24
+            | Self::G729A
+            | Self::ConvediaG729
+            | Self::Sbc24
+            | Self::MediasonicG723
+            | Self::Prosody8Kbps
+            | Self::PhilipsLpcbb
+            | Self::RacalRecorderG7231
+            | Self::RacalRecorderTetraAcelp
+            | Self::VivoG723
+            | Self::VivoSiren
+            | Self::PhilipsCelp
+            | Self::DigitalG723
+            | Self::SiprolabAcelp4800
+            | Self::SiprolabAcelp8V3
+            | Self::SiprolabG729
+            | Self::SiprolabG729A
+            | Self::VoiceageAmr
+            | Self::DictaphoneCelp68
+            | Self::DictaphoneCelp54
+            | Self::QualcommPurevoice
+            | Self::QualcommHalfrate
+            | Self::SycomAcmSyc701Celp54
+            | Self::SycomAcmSyc701Celp68
+            | Self::CreativeFastspeech8
+            | Self::CreativeFastspeech10
+            | Self::IpiRpelp
+            | Self::MicronasCelp833
+            | Self::Olicelp
+            | Self::Olisbc
+            | Self::LhCodecCelp
+            | Self::LhCodecSbc8
+            | Self::LhCodecSbc12
+            | Self::LhCodecSbc16
+            | Self::Voxwarert24Speech
+            | Self::NokiaAdaptiveMultirate
+            | Self::LeadSpeech
+            | Self::AmrNb
+            | Self::AmrWb
+            | Self::AmrWp
+            | Self::GsmAmrCbr
+            | Self::GsmAmrVbrSid
+            | Self::ComverseInfosysG7231
+            | Self::ComverseInfosysAvqsbc
+            | Self::ComverseInfosysSbc
+            | Self::SymbolG729A
+            | Self::VoiceageAmrWb
+            | Self::SpeexVoice
+            | Self::GsmAdaptiveMultirateWb
+            | Self::PolycomG728
+            | Self::PolycomG729A
+            | Self::PolycomSiren
+            | Self::GlobalIpIlbc
+            | Self::VocordG7221
+            | Self::VocordG728
+            | Self::VocordG729
+            | Self::VocordG729A
+            | Self::VocordG7231
+            | Self::VocordLbc
+            | Self::NiceG728
+            | Self::FraceTelecomG729 => Family::Speech,
+            Self::WmaudioLossless
+            | Self::LightwaveLossless
+            | Self::SonicfoundryLossless
+            | Self::WavpackAudio
+            | Self::Alac
+            | Self::Flac => Family::Lossless,
+            _ => Family::Other,
+        }
+    }
+}
+
+/// Broad category of codecs that a format `Tag` belongs to, as returned by `Tag::family`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Family {
+    /// Uncompressed samples, either linear (integer or floating point) or logarithmic (A-law
+    /// and µ-law).
+    Pcm,
+    /// Adaptive differential PCM, including the ITU G.721, G.722 and G.726 codecs.
+    Adpcm,
+    /// MPEG audio layers I, II and III.
+    Mpeg,
+    /// Advanced Audio Coding, in any of its containers.
+    Aac,
+    /// GSM 06.10 and its variants.
+    Gsm,
+    /// Codecs meant for speech, such as CELP, AMR and the ITU G.723, G.728 and G.729.
+    Speech,
+    /// Compressed formats that don't lose information, like FLAC.
+    Lossless,
+    /// Any other format.
+    Other,
+}
+
+/// Speaker positions of the channels in multichannel data, as stored in the `dwChannelMask` of
+/// `WAVE_FORMAT_EXTENSIBLE` formats. The channels appear in the data in the order of their bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ChannelMask(pub u32);
+
+impl ChannelMask {
+    /// Front left speaker.
+    pub const FRONT_LEFT: Self = Self(0x1);
+    /// Front right speaker.
+    pub const FRONT_RIGHT: Self = Self(0x2);
+    /// Front center speaker.
+    pub const FRONT_CENTER: Self = Self(0x4);
+    /// Low frequency effects (subwoofer).
+    pub const LOW_FREQUENCY: Self = Self(0x8);
+    /// Back left speaker.
+    pub const BACK_LEFT: Self = Self(0x10);
+    /// Back right speaker.
+    pub const BACK_RIGHT: Self = Self(0x20);
+    /// Front left of center speaker.
+    pub const FRONT_LEFT_OF_CENTER: Self = Self(0x40);
+    /// Front right of center speaker.
+    pub const FRONT_RIGHT_OF_CENTER: Self = Self(0x80);
+    /// Back center speaker.
+    pub const BACK_CENTER: Self = Self(0x100);
+    /// Side left speaker.
+    pub const SIDE_LEFT: Self = Self(0x200);
+    /// Side right speaker.
+    pub const SIDE_RIGHT: Self = Self(0x400);
+    /// Top center speaker.
+    pub const TOP_CENTER: Self = Self(0x800);
+    /// Top front left speaker.
+    pub const TOP_FRONT_LEFT: Self = Self(0x1000);
+    /// Top front center speaker.
+    pub const TOP_FRONT_CENTER: Self = Self(0x2000);
+    /// Top front right speaker.
+    pub const TOP_FRONT_RIGHT: Self = Self(0x4000);
+    /// Top back left speaker.
+    pub const TOP_BACK_LEFT: Self = Self(0x8000);
+    /// Top back center speaker.
+    pub const TOP_BACK_CENTER: Self = Self(0x10000);
+    /// Top back right speaker.
+    pub const TOP_BACK_RIGHT: Self = Self(0x20000);
+    /// Usual 5.1 layout, with front left, right and center, LFE, and back left and right.
+    pub const SURROUND_5_1: Self = Self(0x3f);
+
+    /// Whether all the speakers in `other` are present in this mask.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Number of speakers present in the mask.
+    pub fn count(self) -> u32 {
+        self.0.count_ones()
+    }
+}
+
+impl BitOr for ChannelMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Inconsistencies found when validating a `Format`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormatError {
+    /// The data has no channels.
+    NoChannels,
+    /// The sample rate is zero.
+    NoSampleRate,
+    /// The bits per sample are not one of 8, 16, 24 or 32 for PCM, or 32 or 64 for IEEE float.
+    BitsPerSample(u16),
+    /// The block alignment does not match the channels and bits per sample.
+    BlockAlign { expected: u32, found: u16 },
+    /// The average data-transfer rate does not match the sample rate and block alignment.
+    AvgBytesPerSec { expected: u64, found: u32 },
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoChannels => write!(f, "channels must be >= 1"),
+            Self::NoSampleRate => write!(f, "samples per second must be >= 1"),
+            Self::BitsPerSample(bits) => {
+                write!(
+                    f,
+                    "bits per sample are not valid for the format tag: {}",
+                    bits
+                )
+            }
+            Self::BlockAlign { expected, found } => write!(
+                f,
+                "block align must be channels * bits per sample / 8 = {}, not {}",
+                expected, found
+            ),
+            Self::AvgBytesPerSec { expected, found } => write!(
+                f,
+                "average bytes per second must be samples per second * block align = {}, not {}",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
 /// Defines the format of waveform-audio data. Only format information common to all
 /// waveform-audio data formats is included in this structure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Format {
     /// Waveform-audio format type.
     pub format_tag: Tag,
@@ -580,29 +935,353 @@ pub struct Format {
 }
 
 impl Format {
-    /// Fill the format structure from the stream of a `.wav` file.
+    /// Describe interleaved PCM data with the given number of channels, sample rate and bit depth.
+    ///
+    /// If the block alignment or the average data-transfer rate don't fit in their fields, they
+    /// are clamped to the largest value that does, so that `validate` rejects the format.
+    ///
+    /// ```
+    /// use winaudio::wave::Format;
+    ///
+    /// let fmt = Format::pcm(4096, 44100, 32);
+    /// assert_eq!(fmt.block_align, 16384);
+    /// assert!(fmt.validate().is_ok());
+    /// assert!(Format::pcm(u16::MAX, 44100, 32).validate().is_err());
+    /// ```
+    pub fn pcm(channels: u16, samples_per_sec: u32, bits_per_sample: u16) -> Self {
+        Self::with_tag(Tag::Pcm, channels, samples_per_sec, bits_per_sample)
+    }
+
+    /// Describe interleaved 32-bit IEEE floating point data with the given number of channels and
+    /// sample rate. Samples are expected to be in the `-1.0..=1.0` range.
+    ///
+    /// ```
+    /// use winaudio::wave::Format;
+    ///
+    /// let fmt = Format::ieee_float(2, 44100);
+    /// assert_eq!(fmt.block_align, 2 * 4);
+    /// assert_eq!(fmt.avg_bytes_per_sec, 44100 * 2 * 4);
+    /// assert!(fmt.validate().is_ok());
+    /// ```
+    pub fn ieee_float(channels: u16, samples_per_sec: u32) -> Self {
+        Self::with_tag(Tag::IeeeFloat, channels, samples_per_sec, 32)
+    }
+
+    pub(crate) fn with_tag(
+        format_tag: Tag,
+        channels: u16,
+        samples_per_sec: u32,
+        bits_per_sample: u16,
+    ) -> Self {
+        let block_align =
+            (channels as u32 * bits_per_sample as u32 / 8).min(u16::MAX as u32) as u16;
+        let avg_bytes_per_sec =
+            (samples_per_sec as u64 * block_align as u64).min(u32::MAX as u64) as u32;
+        Self {
+            format_tag,
+            channels,
+            samples_per_sec,
+            avg_bytes_per_sec,
+            block_align,
+            bits_per_sample,
+        }
+    }
+
+    /// Speaker position of each channel in the default layout for the number of channels, the
+    /// one devices use unless told otherwise, or `None` if there's no usual layout for it.
+    ///
+    /// Files using `WAVE_FORMAT_EXTENSIBLE` may state a different layout, which
+    /// `WavReader::channel_mask` returns.
+    ///
+    /// ```
+    /// use winaudio::wave::format::{ChannelMask, Format};
+    ///
+    /// let stereo = ChannelMask::FRONT_LEFT | ChannelMask::FRONT_RIGHT;
+    /// assert_eq!(Format::pcm(2, 44100, 16).channel_mask(), Some(stereo));
+    /// assert_eq!(Format::pcm(6, 48000, 16).channel_mask(), Some(ChannelMask::SURROUND_5_1));
+    /// assert_eq!(Format::pcm(3, 48000, 16).channel_mask(), None);
+    /// ```
+    pub fn channel_mask(&self) -> Option<ChannelMask> {
+        match self.channels {
+            1 => Some(ChannelMask::FRONT_CENTER),
+            2 => Some(ChannelMask::FRONT_LEFT | ChannelMask::FRONT_RIGHT),
+            4 => Some(
+                ChannelMask::FRONT_LEFT
+                    | ChannelMask::FRONT_RIGHT
+                    | ChannelMask::BACK_LEFT
+                    | ChannelMask::BACK_RIGHT,
+            ),
+            6 => Some(ChannelMask::SURROUND_5_1),
+            8 => Some(ChannelMask::SURROUND_5_1 | ChannelMask::SIDE_LEFT | ChannelMask::SIDE_RIGHT),
+            _ => None,
+        }
+    }
+
+    /// Number of bytes in a frame, made up of one sample for each channel. This is the same as
+    /// `block_align`.
+    pub fn bytes_per_frame(&self) -> u32 {
+        self.block_align as u32
+    }
+
+    /// Number of frames played each second. This is the same as `samples_per_sec`.
+    pub fn frames_per_second(&self) -> u32 {
+        self.samples_per_sec
+    }
+
+    /// Time it takes to play the given amount of bytes, based on `avg_bytes_per_sec`.
+    pub fn bytes_to_duration(&self, bytes: u64) -> Duration {
+        if self.avg_bytes_per_sec == 0 {
+            return Duration::from_secs(0);
+        }
+        Duration::from_secs_f64(bytes as f64 / self.avg_bytes_per_sec as f64)
+    }
+
+    /// Number of bytes needed to play the given duration, rounded down to a whole number of frames
+    /// so that it can be used as an offset or length in the waveform-audio data.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use winaudio::wave::Format;
+    ///
+    /// let fmt = Format::pcm(2, 44100, 16);
+    /// assert_eq!(fmt.duration_to_bytes(Duration::from_secs(1)), 44100 * 4);
+    /// assert_eq!(fmt.duration_to_bytes(Duration::from_micros(30)), 4);
+    /// assert_eq!(fmt.bytes_to_duration(44100 * 4), Duration::from_secs(1));
+    /// ```
+    pub fn duration_to_bytes(&self, duration: Duration) -> u64 {
+        let bytes = (self.avg_bytes_per_sec as f64 * duration.as_secs_f64()) as u64;
+        let align = self.bytes_per_frame().max(1) as u64;
+        bytes - bytes % align
+    }
+
+    /// Fill the format structure from the stream of a `.wav` file, which must start with its
+    /// `fmt ` chunk. Chunks too small to hold the format are rejected, except for the 14-byte
+    /// chunks of very old files, whose bits per sample are derived from the other fields.
+    ///
+    /// ```
+    /// use std::io::{Cursor, ErrorKind};
+    /// use winaudio::wave::Format;
+    ///
+    /// let mut wav = Vec::new();
+    /// wav.extend_from_slice(b"RIFF\0\0\0\0WAVE");
+    /// wav.extend_from_slice(b"fmt \x0e\0\0\0");
+    /// wav.extend_from_slice(&[1, 0, 2, 0, 0x22, 0x56, 0, 0, 0x88, 0x58, 1, 0, 4, 0]);
+    /// wav.extend_from_slice(b"data\0\0\0\0");
+    /// let fmt = Format::from_wav_stream(&mut Cursor::new(wav)).unwrap();
+    /// assert_eq!(fmt, Format::pcm(2, 22050, 16));
+    ///
+    /// let mut wav = Vec::new();
+    /// wav.extend_from_slice(b"RIFF\0\0\0\0WAVE");
+    /// wav.extend_from_slice(b"fmt \x0c\0\0\0");
+    /// wav.extend_from_slice(&[1, 0, 1, 0, 0x40, 0x1f, 0, 0, 0x40, 0x1f, 0, 0]);
+    /// wav.extend_from_slice(b"data\0\0\0\0");
+    ///
+    /// let err = Format::from_wav_stream(&mut Cursor::new(wav)).unwrap_err();
+    /// assert_eq!(err.kind(), ErrorKind::InvalidData);
+    /// ```
     pub fn from_wav_stream<S: Read + Seek>(file: &mut S) -> io::Result<Self> {
-        const WF_OFFSET_FORMATTAG: u64 = 20;
-        file.seek(SeekFrom::Start(WF_OFFSET_FORMATTAG))?;
+        const WF_OFFSET_FMT_SIZE: u64 = 16;
+        file.seek(SeekFrom::Start(WF_OFFSET_FMT_SIZE))?;
+        let size = file.read_u32()?;
+        let mut chunk = Vec::new();
+        file.take(size as u64).read_to_end(&mut chunk)?;
+        Ok(Self::from_chunk(&chunk, false)?.0)
+    }
+
+    /// Fill the format structure from the whole contents of a `fmt ` chunk, checking that it's
+    /// large enough for the fields it should have, including the extension of
+    /// `WAVE_FORMAT_EXTENSIBLE` formats.
+    ///
+    /// Chunks of 14 bytes use the legacy `WAVEFORMAT` layout, without `bits_per_sample`, so it's
+    /// derived from the block alignment and the number of channels.
+    ///
+    /// The channel mask of `WAVE_FORMAT_EXTENSIBLE` formats is returned along with the format,
+    /// if the extension is large enough to have it.
+    pub(crate) fn from_chunk(
+        chunk: &[u8],
+        big_endian: bool,
+    ) -> io::Result<(Self, Option<ChannelMask>)> {
+        if chunk.len() == 14 {
+            let field = |at: usize| {
+                let bytes = [chunk[at], chunk[at + 1]];
+                if big_endian {
+                    u16::from_be_bytes(bytes)
+                } else {
+                    u16::from_le_bytes(bytes)
+                }
+            };
+            let (channels, block_align) = (field(2) as u32, field(12) as u32);
+            let bits = (block_align * 8).checked_div(channels).unwrap_or(0) as u16;
+
+            let mut raw = [0; 16];
+            raw[..14].copy_from_slice(chunk);
+            raw[14..].copy_from_slice(&if big_endian {
+                bits.to_be_bytes()
+            } else {
+                bits.to_le_bytes()
+            });
+            return Ok((Self::read_fields(&mut &raw[..], big_endian)?, None));
+        }
+        if chunk.len() < 16 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("fmt subchunk too small ({} bytes)", chunk.len()),
+            ));
+        }
+        let fmt = Self::read_fields(&mut &chunk[..16], big_endian)?;
+        let mut channel_mask = None;
 
-        Ok(Self {
-            format_tag: file.read_u16()?.try_into().map_err(|tag| {
-                io::Error::new(
+        if fmt.format_tag.raw() == WAVE_FORMAT_EXTENSIBLE {
+            let ext_size = match chunk.get(16..18) {
+                Some(&[a, b]) if big_endian => u16::from_be_bytes([a, b]),
+                Some(&[a, b]) => u16::from_le_bytes([a, b]),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "extensible fmt subchunk missing its extension size",
+                    ))
+                }
+            };
+            if 18 + ext_size as usize > chunk.len() {
+                return Err(io::Error::new(
                     io::ErrorKind::InvalidData,
-                    format!("unknown format tag: {}", tag),
-                )
-            })?,
-            channels: file.read_u16()?,
-            samples_per_sec: file.read_u32()?,
-            avg_bytes_per_sec: file.read_u32()?,
-            block_align: file.read_u16()?,
-            bits_per_sample: file.read_u16()?,
-        })
+                    format!(
+                        "fmt subchunk extension ({} bytes) larger than the subchunk ({} bytes)",
+                        ext_size,
+                        chunk.len()
+                    ),
+                ));
+            }
+
+            // The mask comes after the valid bits per sample.
+            if ext_size >= 6 {
+                let mask = [chunk[20], chunk[21], chunk[22], chunk[23]];
+                channel_mask = Some(ChannelMask(if big_endian {
+                    u32::from_be_bytes(mask)
+                } else {
+                    u32::from_le_bytes(mask)
+                }));
+            }
+        }
+        Ok((fmt, channel_mask))
+    }
+
+    fn read_fields<R: Read>(reader: &mut R, big_endian: bool) -> io::Result<Self> {
+        let mut raw = [0; 16];
+        reader.read_exact(&mut raw)?;
+        if big_endian {
+            // Reverse each of the fields so that they can be read as little-endian.
+            let mut start = 0;
+            for size in [2, 2, 4, 4, 2, 2].iter() {
+                raw[start..start + size].reverse();
+                start += size;
+            }
+        }
+
+        let mut raw = &raw[..];
+        let fmt = Self {
+            format_tag: raw.read_u16()?.into(),
+            channels: raw.read_u16()?,
+            samples_per_sec: raw.read_u32()?,
+            avg_bytes_per_sec: raw.read_u32()?,
+            block_align: raw.read_u16()?,
+            bits_per_sample: raw.read_u16()?,
+        };
+        fmt.validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        Ok(fmt)
+    }
+
+    /// Check that the fields describing the layout of PCM and IEEE float data are consistent
+    /// with each other. Other formats are not checked, as their layout depends on the codec.
+    pub fn validate(&self) -> Result<(), FormatError> {
+        match self.format_tag {
+            Tag::Pcm | Tag::IeeeFloat => {}
+            _ => return Ok(()),
+        }
+
+        if self.channels == 0 {
+            return Err(FormatError::NoChannels);
+        }
+        if self.samples_per_sec == 0 {
+            return Err(FormatError::NoSampleRate);
+        }
+        match (self.format_tag, self.bits_per_sample) {
+            (Tag::Pcm, 8) | (Tag::Pcm, 16) | (Tag::Pcm, 24) | (Tag::Pcm, 32) => {}
+            (Tag::IeeeFloat, 32) | (Tag::IeeeFloat, 64) => {}
+            (_, bits) => return Err(FormatError::BitsPerSample(bits)),
+        }
+
+        let block_align = self.channels as u32 * self.bits_per_sample as u32 / 8;
+        if self.block_align as u32 != block_align {
+            return Err(FormatError::BlockAlign {
+                expected: block_align,
+                found: self.block_align,
+            });
+        }
+
+        let avg_bytes_per_sec = self.samples_per_sec as u64 * self.block_align as u64;
+        if self.avg_bytes_per_sec as u64 != avg_bytes_per_sec {
+            return Err(FormatError::AvgBytesPerSec {
+                expected: avg_bytes_per_sec,
+                found: self.avg_bytes_per_sec,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Create a format from the `WAVEFORMATEX` given by another API, such as WASAPI or
+    /// DirectSound, which is then validated like `validate` does.
+    ///
+    /// Only the fields of the `WAVEFORMATEX` itself are read, so for `WAVE_FORMAT_EXTENSIBLE`
+    /// the format tag is `Tag::Other(0xfffe)` and the channel mask is not known. Those are only
+    /// read from a whole `fmt ` chunk, such as by `from_wav_stream`.
+    ///
+    /// ```
+    /// use winapi::shared::mmreg::{WAVEFORMATEX, WAVE_FORMAT_PCM};
+    /// use winaudio::wave::Format;
+    ///
+    /// let raw = WAVEFORMATEX {
+    ///     wFormatTag: WAVE_FORMAT_PCM,
+    ///     nChannels: 2,
+    ///     nSamplesPerSec: 44100,
+    ///     nAvgBytesPerSec: 176400,
+    ///     nBlockAlign: 4,
+    ///     wBitsPerSample: 16,
+    ///     cbSize: 0,
+    /// };
+    /// let fmt = Format::from_c_struct(&raw).unwrap();
+    /// assert_eq!(fmt, Format::pcm(2, 44100, 16));
+    /// assert!(fmt.matches(&raw));
+    /// ```
+    pub fn from_c_struct(raw: &WAVEFORMATEX) -> Result<Self, FormatError> {
+        let fmt = Self {
+            format_tag: Tag::from(raw.wFormatTag),
+            channels: raw.nChannels,
+            samples_per_sec: raw.nSamplesPerSec,
+            avg_bytes_per_sec: raw.nAvgBytesPerSec,
+            block_align: raw.nBlockAlign,
+            bits_per_sample: raw.wBitsPerSample,
+        };
+        fmt.validate()?;
+        Ok(fmt)
+    }
+
+    /// Whether the `WAVEFORMATEX` given by another API describes this same format. Only the
+    /// fields of the `WAVEFORMATEX` itself are compared, so the channel mask is ignored.
+    pub fn matches(&self, raw: &WAVEFORMATEX) -> bool {
+        self.format_tag.raw() == raw.wFormatTag
+            && self.channels == raw.nChannels
+            && self.samples_per_sec == raw.nSamplesPerSec
+            && self.avg_bytes_per_sec == raw.nAvgBytesPerSec
+            && self.block_align == raw.nBlockAlign
+            && self.bits_per_sample == raw.wBitsPerSample
     }
 
     pub(crate) fn c_struct(&self) -> WAVEFORMATEX {
         WAVEFORMATEX {
-            wFormatTag: self.format_tag as u16,
+            wFormatTag: self.format_tag.raw(),
             nChannels: self.channels,
             nSamplesPerSec: self.samples_per_sec,
             nAvgBytesPerSec: self.avg_bytes_per_sec,
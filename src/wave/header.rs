@@ -0,0 +1,75 @@
+//! Writing of `.wav` file headers.
+use crate::util::BinaryWrite as _;
+use crate::wave::Format;
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Size of the header written by `write_wav_header`, after which the data begins.
+const HEADER_LEN: u64 = 44;
+const WF_OFFSET_RIFF_SIZE: u64 = 4;
+const WF_OFFSET_DATA_SIZE: u64 = 40;
+
+/// Write the header of a `.wav` file with the given format, consisting of the `RIFF` chunk
+/// with the `fmt ` and `data` subchunks. The data should be written right after it.
+///
+/// If the length of the data isn't known upfront, zero can be used, and the sizes can be
+/// fixed later with `finish_wav_header` once all the data has been written.
+///
+/// ```
+/// use std::io::{Cursor, Write};
+/// use winaudio::wave::{self, Format};
+///
+/// let fmt = Format::pcm(2, 44100, 16);
+/// let mut file = Cursor::new(Vec::new());
+/// wave::write_wav_header(&mut file, &fmt, 0).unwrap();
+/// file.write_all(&[0; 1024]).unwrap();
+/// wave::finish_wav_header(&mut file).unwrap();
+///
+/// assert_eq!(file.get_ref().len(), 44 + 1024);
+/// assert_eq!(Format::from_wav_stream(&mut file).unwrap(), fmt);
+/// ```
+pub fn write_wav_header<W: Write + Seek>(
+    writer: &mut W,
+    fmt: &Format,
+    data_len: u32,
+) -> io::Result<()> {
+    writer.write_all(b"RIFF")?;
+    writer.write_u32(data_len.saturating_add(HEADER_LEN as u32 - 8))?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_u32(16)?;
+    writer.write_u16(fmt.format_tag.raw())?;
+    writer.write_u16(fmt.channels)?;
+    writer.write_u32(fmt.samples_per_sec)?;
+    writer.write_u32(fmt.avg_bytes_per_sec)?;
+    writer.write_u16(fmt.block_align)?;
+    writer.write_u16(fmt.bits_per_sample)?;
+
+    writer.write_all(b"data")?;
+    writer.write_u32(data_len)
+}
+
+/// Fix the sizes in a header written by `write_wav_header` to match the amount of data that
+/// follows it. The stream is left positioned at its end.
+pub fn finish_wav_header<W: Write + Seek>(writer: &mut W) -> io::Result<()> {
+    let file_len = writer.seek(SeekFrom::End(0))?;
+    let data_len = file_len.checked_sub(HEADER_LEN).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "stream is shorter than the header",
+        )
+    })?;
+    if file_len > u32::MAX as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "stream is too large for a wav file",
+        ));
+    }
+
+    writer.seek(SeekFrom::Start(WF_OFFSET_RIFF_SIZE))?;
+    writer.write_u32(file_len as u32 - 8)?;
+    writer.seek(SeekFrom::Start(WF_OFFSET_DATA_SIZE))?;
+    writer.write_u32(data_len as u32)?;
+    writer.seek(SeekFrom::End(0))?;
+    Ok(())
+}
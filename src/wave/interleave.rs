@@ -0,0 +1,55 @@
+//! Conversion between planar and interleaved sample layouts.
+
+/// Interleave planar samples, one slice per channel, into a single buffer of frames
+/// suitable for writing to an output device.
+///
+/// # Panics
+///
+/// Panics if the channels don't all have the same length.
+///
+/// ```
+/// use winaudio::wave::interleave;
+///
+/// let left = [0.1, 0.2];
+/// let right = [0.3, 0.4];
+/// assert_eq!(interleave(&[&left, &right]), vec![0.1, 0.3, 0.2, 0.4]);
+/// ```
+pub fn interleave(channels: &[&[f32]]) -> Vec<f32> {
+    let len = channels.first().map(|c| c.len()).unwrap_or(0);
+    assert!(
+        channels.iter().all(|c| c.len() == len),
+        "all channels must have the same length"
+    );
+
+    let mut samples = Vec::with_capacity(len * channels.len());
+    for i in 0..len {
+        samples.extend(channels.iter().map(|c| c[i]));
+    }
+    samples
+}
+
+/// Split interleaved frames into planar samples, one vector per channel.
+///
+/// # Panics
+///
+/// Panics if the number of channels is zero, or if the number of samples is not a multiple of
+/// it.
+///
+/// ```
+/// use winaudio::wave::deinterleave;
+///
+/// let planar = deinterleave(&[0.1, 0.3, 0.2, 0.4], 2);
+/// assert_eq!(planar, vec![vec![0.1, 0.2], vec![0.3, 0.4]]);
+/// ```
+pub fn deinterleave(samples: &[f32], channels: u16) -> Vec<Vec<f32>> {
+    let channels = channels as usize;
+    assert!(channels != 0, "there must be at least one channel");
+    assert!(
+        samples.len().is_multiple_of(channels),
+        "the samples must be whole frames"
+    );
+
+    (0..channels)
+        .map(|c| samples.iter().skip(c).step_by(channels).copied().collect())
+        .collect()
+}
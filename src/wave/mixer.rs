@@ -0,0 +1,166 @@
+//! Software mixing of several sources into a single output device.
+use crate::device::WAVE_MAPPER;
+use crate::util::read_chunk;
+use crate::wave::format::Tag;
+use crate::wave::{Format, Out};
+use crate::Error;
+use std::convert::TryInto;
+use std::io::{self, Read};
+
+/// Identifies a source added to a `Mixer`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SourceId(usize);
+
+/// Plays several sources of raw waveform-audio data at once, by summing their samples into a
+/// single output device. All sources must share the format the mixer was created with, which
+/// must be either 16-bit `Tag::Pcm` or 32-bit `Tag::IeeeFloat`.
+///
+/// ```no_run
+/// use std::fs::File;
+/// use winaudio::wave::{Format, Mixer};
+///
+/// let mut mixer = Mixer::new(Format::pcm(2, 44100, 16)).unwrap();
+/// mixer.add_source(Box::new(File::open("music.raw").unwrap()));
+/// mixer.add_source(Box::new(File::open("effect.raw").unwrap()));
+/// mixer.play().unwrap();
+/// ```
+pub struct Mixer {
+    out: Out,
+    fmt: Format,
+    sources: Vec<(SourceId, Box<dyn Read>)>,
+    next_id: usize,
+    chunk: Vec<u8>,
+    mix: Vec<f32>,
+}
+
+impl Mixer {
+    /// How many chunks of audio are mixed each second.
+    const CHUNKS_PER_SEC: u32 = 10;
+
+    /// Open the output device selected by `device::WAVE_MAPPER` to mix sources with the given
+    /// format. Returns `Error::BadFormat` if the format can't be mixed.
+    pub fn new(fmt: Format) -> Result<Self, Error> {
+        match (fmt.format_tag, fmt.bits_per_sample) {
+            (Tag::Pcm, 16) | (Tag::IeeeFloat, 32) => {}
+            _ => return Err(Error::BadFormat),
+        }
+        let out = Out::open(WAVE_MAPPER, &fmt)?;
+
+        let frames = (fmt.samples_per_sec / Self::CHUNKS_PER_SEC).max(1) as usize;
+        let chunk = vec![0; frames * fmt.block_align as usize];
+        let mix = vec![0.0; chunk.len() / (fmt.bits_per_sample / 8) as usize];
+        Ok(Self {
+            out,
+            fmt,
+            sources: Vec::new(),
+            next_id: 0,
+            chunk,
+            mix,
+        })
+    }
+
+    /// Add a new source of raw data to be mixed, starting with the next chunk.
+    pub fn add_source(&mut self, source: Box<dyn Read>) -> SourceId {
+        let id = SourceId(self.next_id);
+        self.next_id += 1;
+        self.sources.push((id, source));
+        id
+    }
+
+    /// Stop mixing the given source, returning it if it had not reached its end yet.
+    pub fn remove_source(&mut self, id: SourceId) -> Option<Box<dyn Read>> {
+        let index = self.sources.iter().position(|(i, _)| *i == id)?;
+        Some(self.sources.remove(index).1)
+    }
+
+    /// Number of sources currently being mixed.
+    pub fn source_count(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Mix the next chunk of every source and write it to the output device, waiting for the
+    /// device to be done with one of its buffers. The chunks written before keep playing in the
+    /// meantime, so there's no gap between them. Sources that reach their end are removed.
+    ///
+    /// Returns `false` if there were no sources left to mix, in which case nothing is written.
+    pub fn mix_next(&mut self) -> io::Result<bool> {
+        if self.sources.is_empty() {
+            return Ok(false);
+        }
+
+        let sample_size = (self.fmt.bits_per_sample / 8) as usize;
+        let align = self.fmt.block_align as usize;
+        let mut len = 0;
+        self.mix.iter_mut().for_each(|x| *x = 0.0);
+
+        let mut i = 0;
+        while i < self.sources.len() {
+            let read = read_chunk(&mut self.sources[i].1, &mut self.chunk)?;
+            let read = read - read % align;
+            for (sum, sample) in self
+                .mix
+                .iter_mut()
+                .zip(self.chunk[..read].chunks(sample_size))
+            {
+                *sum += match self.fmt.format_tag {
+                    Tag::IeeeFloat => f32::from_le_bytes(sample.try_into().unwrap()),
+                    _ => i16::from_le_bytes(sample.try_into().unwrap()) as f32,
+                };
+            }
+            len = len.max(read);
+
+            if read < self.chunk.len() {
+                self.sources.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        for (sample, sum) in self.chunk[..len]
+            .chunks_mut(sample_size)
+            .zip(self.mix.iter())
+        {
+            match self.fmt.format_tag {
+                Tag::IeeeFloat => sample.copy_from_slice(&sum.clamp(-1.0, 1.0).to_le_bytes()),
+                _ => sample.copy_from_slice(
+                    &(sum.clamp(i16::MIN as f32, i16::MAX as f32) as i16).to_le_bytes(),
+                ),
+            }
+        }
+        if len != 0 {
+            self.out.write_chunked(&self.chunk[..len])?;
+        }
+        Ok(true)
+    }
+
+    /// Mix all sources until every one of them reaches its end, and wait for the output device
+    /// to finish playing.
+    pub fn play(&mut self) -> io::Result<()> {
+        while self.mix_next()? {}
+        self.out.wait();
+        Ok(())
+    }
+
+    /// Number of times the output device ran out of mixed data to play, as with
+    /// `Out::underrun_count`. Each chunk is queued while the previous one is still playing, so
+    /// this stays at zero unless mixing is slower than playing.
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use winaudio::device;
+    /// use winaudio::wave::{Format, Mixer};
+    ///
+    /// # // Skip instead of failing where there are no devices at all, not even a null one.
+    /// # if device::count() == 0 {
+    /// #     return;
+    /// # }
+    /// let mut mixer = Mixer::new(Format::pcm(2, 44100, 16)).unwrap();
+    /// mixer.add_source(Box::new(Cursor::new(vec![0; 44100 * 4])));
+    /// mixer.add_source(Box::new(Cursor::new(vec![0; 44100 * 4])));
+    /// mixer.play().unwrap();
+    /// assert_eq!(mixer.underrun_count(), 0);
+    /// ```
+    pub fn underrun_count(&self) -> u64 {
+        self.out.underrun_count()
+    }
+}
@@ -1,10 +1,38 @@
 //! Access to wave output devices.
+mod bext;
 mod buffer;
+mod control;
+pub mod convert;
+#[cfg(feature = "cpal-interop")]
+mod cpal_interop;
+#[cfg(feature = "decode")]
+mod decode;
 pub mod format;
+mod header;
+mod interleave;
+mod mixer;
 mod out;
 mod player;
+mod reader;
+mod resample;
+mod sample;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod tone;
 
-pub use buffer::Buffer;
+pub use bext::BroadcastExtension;
+pub use buffer::{split_into_buffers, Buffer, BufferPool, ReadOutcome};
+pub use control::PlaybackHandle;
+pub use convert::{from_fixed_16_16, raw_to_volume, to_fixed_16_16, volume_to_raw};
+#[cfg(feature = "decode")]
+pub use decode::Decoder;
 pub use format::Format;
-pub use out::Out;
-pub use player::Player;
+pub use header::{finish_wav_header, write_wav_header};
+pub use interleave::{deinterleave, interleave};
+pub use mixer::{Mixer, SourceId};
+pub use out::{OpenError, Out, VolumeCurve, WaveOut};
+pub use player::{crossfade, Player};
+pub use reader::WavReader;
+pub use resample::resample;
+pub use sample::Sample;
+pub use tone::tone;
@@ -1,154 +1,1096 @@
-use crate::util::{check_multimedia_error, Event};
-use crate::wave::{Buffer, Format};
+use crate::device;
+use crate::util::{check_multimedia_error_ctx, record_error_context, Event};
+use crate::wave::convert::{
+    f32_to_i16, from_fixed_16_16, raw_to_volume, to_fixed_16_16, volume_to_raw,
+};
+use crate::wave::format::{FormatError, Tag};
+use crate::wave::{split_into_buffers, Buffer, BufferPool, Format, Sample};
 use crate::Error;
+use std::fmt;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::io;
 use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 use std::pin::Pin;
 use std::ptr;
+use std::sync::{Mutex, PoisonError};
+#[cfg(feature = "async")]
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
 use winapi::um::mmeapi::{
-    waveOutClose, waveOutOpen, waveOutPause, waveOutPrepareHeader, waveOutRestart,
-    waveOutSetVolume, waveOutUnprepareHeader, waveOutWrite, waveOutReset,
+    waveOutClose, waveOutGetPitch, waveOutGetPlaybackRate, waveOutGetPosition, waveOutGetVolume,
+    waveOutOpen, waveOutPause, waveOutPrepareHeader, waveOutReset, waveOutRestart, waveOutSetPitch,
+    waveOutSetPlaybackRate, waveOutSetVolume, waveOutUnprepareHeader, waveOutWrite,
+};
+use winapi::um::mmsystem::{
+    CALLBACK_FUNCTION, HWAVEOUT, MMRESULT, MMSYSERR_NOERROR, MMTIME, TIME_BYTES, TIME_SAMPLES,
+    WAVEHDR, WOM_CLOSE, WOM_DONE, WOM_OPEN,
 };
-use winapi::um::mmsystem::{CALLBACK_FUNCTION, HWAVEOUT, WAVEHDR, WOM_CLOSE, WOM_DONE, WOM_OPEN};
-use winapi::um::winnt::LPSTR;
+
+// Not available in `winapi`.
+#[link(name = "winmm")]
+extern "system" {
+    fn waveOutGetID(hwo: HWAVEOUT, device_id: *mut u32) -> MMRESULT;
+}
 
 const HDR_SIZE: u32 = mem::size_of::<WAVEHDR>() as u32;
 const WHDR_PREPARED: u32 = 0x00000002;
+const WHDR_INQUEUE: u32 = 0x00000010;
+
+/// Function called with the index of each buffer the device is done playing.
+type OnBufferDone = Box<dyn FnMut(usize) + Send>;
 
-extern "C" fn callback(_hwo: HWAVEOUT, msg: u32, instance: usize, _param1: usize, _param2: usize) {
+/// State shared with the callback, which runs on a thread owned by the system.
+struct Shared {
+    done: Event,
+    on_buffer_done: Mutex<Option<OnBufferDone>>,
+    // Task waiting on a `Drain` future, to be woken up when any buffer is done.
+    #[cfg(feature = "async")]
+    waker: Mutex<Option<Waker>>,
+}
+
+extern "C" fn callback(_hwo: HWAVEOUT, msg: u32, instance: usize, param1: usize, _param2: usize) {
+    // Unwinding out of the callback is undefined behaviour, and a panic may still come from the
+    // function registered by the user, so it's caught and discarded.
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| handle_message(msg, instance, param1)));
+}
+
+fn handle_message(msg: u32, instance: usize, param1: usize) {
     match msg {
         WOM_OPEN | WOM_CLOSE => {}
         WOM_DONE => {
-            let event = unsafe { &mut *(instance as *mut Event) };
-            event.set();
+            let shared = unsafe { &*(instance as *const Shared) };
+            shared.done.set();
+            #[cfg(feature = "async")]
+            {
+                let waker = shared
+                    .waker
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .take();
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+
+            // The header is packed, so the field is read without creating a reference to it.
+            let index =
+                unsafe { ptr::read_unaligned(ptr::addr_of!((*(param1 as *const WAVEHDR)).dwUser)) };
+            let mut on_buffer_done = shared
+                .on_buffer_done
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if let Some(f) = on_buffer_done.as_mut() {
+                f(index);
+            }
+        }
+        // Don't panic on messages that are not documented, just ignore them.
+        _ => {}
+    }
+}
+
+/// How a volume level from a user interface, such as the position of a slider, maps to the
+/// volume of the device.
+///
+/// The loudness that people perceive is roughly logarithmic, so with a linear mapping most of
+/// the change is heard near the bottom, and the top of the range barely sounds different.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VolumeCurve {
+    /// The level is used as the volume as-is.
+    Linear,
+    /// The level is spread over the `VolumeCurve::DYNAMIC_RANGE_DB` decibels below full volume,
+    /// so each step changes the loudness by the same amount. A level of 0.0 is still silence.
+    Logarithmic,
+    /// The volume is the square of the level, which is a cheap approximation of
+    /// `VolumeCurve::Logarithmic` that also reaches silence smoothly.
+    SquareLaw,
+}
+
+impl VolumeCurve {
+    /// Decibels covered by `VolumeCurve::Logarithmic`, from full volume down to the lowest
+    /// level above silence.
+    pub const DYNAMIC_RANGE_DB: f32 = 60.0;
+
+    /// Map a level in the `0.0..=1.0` range to a linear volume in the same range.
+    ///
+    /// ```
+    /// use winaudio::wave::VolumeCurve;
+    ///
+    /// assert_eq!(VolumeCurve::SquareLaw.apply(0.5), 0.25);
+    /// assert_eq!(VolumeCurve::Logarithmic.apply(1.0), 1.0);
+    /// assert_eq!(VolumeCurve::Logarithmic.apply(0.0), 0.0);
+    /// assert!((VolumeCurve::Logarithmic.apply(2.0 / 3.0) - 0.1).abs() < 1e-6);
+    /// ```
+    pub fn apply(self, level: f32) -> f32 {
+        match self {
+            Self::Linear => level,
+            Self::Logarithmic if level <= 0.0 => 0.0,
+            Self::Logarithmic => 10f32.powf((level - 1.0) * Self::DYNAMIC_RANGE_DB / 20.0),
+            Self::SquareLaw => level * level,
         }
-        _ => panic!("unexpected callback message"),
     }
 }
 
 /// Access to a wave output device.
+///
+/// Dropping it stops the playback right away, cutting off anything that was still queued. Use
+/// `detach` to let the device finish playing first.
 pub struct Out {
     hwo: HWAVEOUT,
-    cb_done: Pin<Box<Event>>,
+    fmt: Format,
+    // Capabilities of the device, if they could be queried when it was opened.
+    caps: Option<device::Capabilities>,
+    shared: Pin<Box<Shared>>,
     // The buffers must remain valid while the device is playing them,
     // and unless we own them they could be dropped at any time.
     // This also means that their lifecycle has to be handled manually.
     buffers: [Buffer; 2],
+    // Index of the buffer that will be used by the next write,
+    // and how many bytes have been copied into it so far.
+    next: usize,
+    pending: usize,
+    // Whether the device is expected to be playing, and how many times it ran out of data.
+    playing: bool,
+    underruns: u64,
+    // How long the last write waited for a free buffer, and the moving average of those waits.
+    last_wait: Duration,
+    average_wait: Duration,
+    // Position of the device when the current playback started, to report progress from there.
+    baseline: u32,
+    // Whether the playback was paused, in which case queued buffers won't complete.
+    paused: bool,
+    // Whether the device was already closed through `close`, so that dropping does nothing.
+    closed: bool,
+    // Where to give the memory of the buffers back to once they're unprepared.
+    pool: Option<BufferPool>,
 }
 
 impl Out {
     /// Individual buffer size for each of the two buffers.
-    const BUFFER_SIZE: usize = 256 * 1024;
+    pub(crate) const BUFFER_SIZE: usize = 256 * 1024;
+    /// Smallest buffer size to fall back to if there's not enough memory for `BUFFER_SIZE`.
+    const MIN_BUFFER_SIZE: usize = 16 * 1024;
 
     /// Opens the specified waveform-audio output device for playback.
     ///
     /// The waveform-audio output device identifier is a number in the range `0..device::count()`.
     /// The `device::WAVE_MAPPER` may also be used to automatically select a compatible device.
+    ///
+    /// Returns `Error::BadDeviceId` without trying to open the device if the identifier is neither
+    /// of these, and `Error::BadFormat` if `Format::validate` fails. `try_open` tells what is wrong
+    /// with the format in the latter case.
+    ///
+    /// If there's not enough memory for the buffers, smaller ones are used instead, down to
+    /// 16 KiB each. `buffer_size` tells which size was used, and `ErrorContext::last` keeps the
+    /// `Error::NoMemory` that caused the fallback.
+    ///
+    /// ```
+    /// use winaudio::device::WAVE_MAPPER;
+    /// use winaudio::wave::{Format, Out};
+    /// use winaudio::Error;
+    ///
+    /// let fmt = Format::pcm(0, 44100, 16);
+    /// assert_eq!(Out::open(WAVE_MAPPER, &fmt).err(), Some(Error::BadFormat));
+    /// ```
     pub fn open(device_id: u32, fmt: &Format) -> Result<Self, Error> {
-        let cb_done = Box::pin(Event::new());
-        (*cb_done).set(); // start ready
+        Self::open_in(device_id, fmt, None).map_err(Error::from)
+    }
+
+    /// Like `open`, but if `Format::validate` fails, the reason is returned as
+    /// `OpenError::Format` instead of a plain `Error::BadFormat`. Any other error comes from the
+    /// device and is returned as `OpenError::Device`.
+    ///
+    /// ```
+    /// use winaudio::device::WAVE_MAPPER;
+    /// use winaudio::wave::format::FormatError;
+    /// use winaudio::wave::{Format, OpenError, Out};
+    ///
+    /// let err = Out::try_open(WAVE_MAPPER, &Format::pcm(0, 44100, 16)).err().unwrap();
+    /// assert_eq!(err, OpenError::Format(FormatError::NoChannels));
+    /// assert_eq!(err.to_string(), "channels must be >= 1");
+    /// ```
+    pub fn try_open(device_id: u32, fmt: &Format) -> Result<Self, OpenError> {
+        Self::open_in(device_id, fmt, None)
+    }
+
+    /// Like `open`, but the memory for the buffers is taken from the pool if it has any of the
+    /// right size, and given back to it when the device is closed or dropped.
+    pub fn open_with_pool(pool: &BufferPool, device_id: u32, fmt: &Format) -> Result<Self, Error> {
+        Self::open_in(device_id, fmt, Some(pool.clone())).map_err(Error::from)
+    }
+
+    fn open_in(device_id: u32, fmt: &Format, pool: Option<BufferPool>) -> Result<Self, OpenError> {
+        if !device::is_valid_id(device_id) {
+            return Err(Error::BadDeviceId.into());
+        }
+        fmt.validate().map_err(OpenError::Format)?;
+        let caps = device::get_capabilities(device_id).ok();
+
+        let shared = Box::pin(Shared {
+            done: Event::new(),
+            on_buffer_done: Mutex::new(None),
+            #[cfg(feature = "async")]
+            waker: Mutex::new(None),
+        });
+        shared.done.set(); // start ready
 
         let mut hwo: HWAVEOUT = ptr::null_mut();
-        check_multimedia_error(unsafe {
-            waveOutOpen(
-                &mut hwo,
-                device_id,
-                &fmt.c_struct(),
-                callback as usize,
-                &*cb_done as *const Event as usize,
-                CALLBACK_FUNCTION,
-            )
-        })?;
+        check_multimedia_error_ctx(
+            unsafe {
+                waveOutOpen(
+                    &mut hwo,
+                    device_id,
+                    &fmt.c_struct(),
+                    callback as *const () as usize,
+                    &*shared as *const Shared as usize,
+                    CALLBACK_FUNCTION,
+                )
+            },
+            "waveOutOpen",
+        )?;
 
         let align = fmt.block_align as usize;
-        let new_block = || match Self::prepare_block(hwo, align, Self::BUFFER_SIZE) {
-            Ok(x) => Ok(x),
+        let [mut first, mut second] = match Self::prepare_buffers(hwo, align, pool.as_ref()) {
+            Ok(buffers) => buffers,
             Err(e) => {
                 unsafe { waveOutClose(hwo) };
-                Err(e)
+                return Err(e.into());
             }
         };
-        let first = new_block()?;
-        let second = new_block()?;
+        // The index of each buffer is reported back in the callback.
+        first.header.dwUser = 0;
+        second.header.dwUser = 1;
 
         Ok(Self {
             hwo,
-            cb_done,
+            fmt: *fmt,
+            caps,
+            shared,
             buffers: [first, second],
+            next: 0,
+            pending: 0,
+            playing: false,
+            underruns: 0,
+            last_wait: Duration::from_secs(0),
+            average_wait: Duration::from_secs(0),
+            baseline: 0,
+            paused: false,
+            closed: false,
+            pool,
         })
     }
 
+    /// Like `open`, but if the device is currently allocated by someone else (or fails with any
+    /// other `Error::is_transient` error), keep retrying with a short backoff until it's freed up
+    /// or the timeout elapses. In the latter case, the last error is returned. Any other error is
+    /// returned immediately.
+    pub fn open_blocking(device_id: u32, fmt: &Format, timeout: Duration) -> Result<Self, Error> {
+        const MIN_BACKOFF: Duration = Duration::from_millis(10);
+        const MAX_BACKOFF: Duration = Duration::from_millis(250);
+
+        let start = Instant::now();
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            match Self::open(device_id, fmt) {
+                Err(e) if e.is_transient() => {
+                    let elapsed = start.elapsed();
+                    if elapsed >= timeout {
+                        return Err(e);
+                    }
+                    thread::sleep(backoff.min(timeout - elapsed));
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Like `open`, but if the device can't play the desired format, open it with the closest
+    /// standard format it supports instead, with the same number of channels and the nearest
+    /// sample rate and bit depth. The format that was actually used is returned along with it.
+    ///
+    /// The data written must be converted to the returned format by the caller. Otherwise, if
+    /// the sample rate differs, it will play at the wrong pitch and speed, which is why this is
+    /// not the default behaviour. Returns `Error::BadFormat` if there is no suitable format.
+    pub fn open_best_match(device_id: u32, desired: &Format) -> Result<(Self, Format), Error> {
+        match Self::open(device_id, desired) {
+            Err(Error::BadFormat) => {}
+            result => return result.map(|out| (out, *desired)),
+        }
+
+        let best = device::get_capabilities(device_id)?
+            .supported_formats()
+            .into_iter()
+            .map(Format::from)
+            .filter(|fmt| fmt.channels == desired.channels)
+            .min_by_key(|fmt| {
+                (
+                    (fmt.samples_per_sec as i64 - desired.samples_per_sec as i64).abs(),
+                    (fmt.bits_per_sample as i32 - desired.bits_per_sample as i32).abs(),
+                )
+            })
+            .ok_or(Error::BadFormat)?;
+
+        Self::open(device_id, &best).map(|out| (out, best))
+    }
+
+    /// Format the device was opened with, which is the format the data written to it must be in.
+    ///
+    /// ```no_run
+    /// use winaudio::device::WAVE_MAPPER;
+    /// use winaudio::wave::{Format, Out};
+    ///
+    /// let fmt = Format::pcm(2, 44100, 16);
+    /// let (out, best) = Out::open_best_match(WAVE_MAPPER, &fmt).unwrap();
+    /// assert_eq!(out.format(), best);
+    /// ```
+    pub fn format(&self) -> Format {
+        self.fmt
+    }
+
+    /// Sample rate the device was opened with, in hertz.
+    pub fn sample_rate(&self) -> u32 {
+        self.fmt.samples_per_sec
+    }
+
+    /// Identifier of the device that was opened, in the range `0..device::count()`. When it was
+    /// opened with `device::WAVE_MAPPER`, the device that was selected can be found this way.
+    pub fn device_id(&self) -> Result<u32, Error> {
+        let mut id = 0;
+        check_multimedia_error_ctx(unsafe { waveOutGetID(self.hwo, &mut id) }, "waveOutGetID")?;
+        Ok(id)
+    }
+
+    /// Send a message to the driver of the device, for driver-specific functionality that is
+    /// not otherwise reachable. The value returned by the driver is not interpreted in any way,
+    /// as its meaning depends on the message being sent.
+    ///
+    /// # Safety
+    ///
+    /// The parameters are passed to the driver as-is, and depending on the message they may be
+    /// used as pointers. They must be valid for whatever the driver does with them.
+    #[cfg(feature = "interop")]
+    pub unsafe fn message(&mut self, msg: u32, param1: usize, param2: usize) -> u32 {
+        winapi::um::mmeapi::waveOutMessage(self.hwo, msg, param1, param2)
+    }
+
     /// New volume setting. A value of 1.0 represents full volume, and 0.0 silence.
     ///
     /// If a device does not support both left and right volume control, the
     /// left volume level will be used, and the right volume level is ignored.
     pub fn set_volume(&mut self, left: f32, right: f32) -> Result<(), Error> {
-        if left < 0.0 || left > 1.0 || right < 0.0 || right > 1.0 {
+        // Written this way, NaN is out of range too.
+        if !(0.0..=1.0).contains(&left) || !(0.0..=1.0).contains(&right) {
+            return Err(Error::InvalidParam);
+        }
+        let vol = volume_to_raw(left) | (volume_to_raw(right) << 16);
+        check_multimedia_error_ctx(
+            unsafe { waveOutSetVolume(self.hwo, vol) },
+            "waveOutSetVolume",
+        )
+    }
+
+    /// Current volume setting, as `(left, right)`. A value of 1.0 represents full volume, and
+    /// 0.0 silence.
+    ///
+    /// If the device does not support `Functionality::LrVolume`, it only has a single volume,
+    /// which is returned for both channels.
+    pub fn get_volume(&self) -> Result<(f32, f32), Error> {
+        let mut vol = 0;
+        check_multimedia_error_ctx(
+            unsafe { waveOutGetVolume(self.hwo, &mut vol) },
+            "waveOutGetVolume",
+        )?;
+        let left = raw_to_volume(vol & 0xffff);
+        match &self.caps {
+            Some(caps) if !caps.supports(device::Functionality::LrVolume) => Ok((left, left)),
+            _ => Ok((left, raw_to_volume(vol >> 16))),
+        }
+    }
+
+    /// Set the volume of both channels from a level in the `0.0..=1.0` range, mapped through
+    /// the given curve. `set_volume` is the same as using `VolumeCurve::Linear`.
+    pub fn set_volume_curve(&mut self, level: f32, curve: VolumeCurve) -> Result<(), Error> {
+        if !(0.0..=1.0).contains(&level) {
+            return Err(Error::InvalidParam);
+        }
+        let volume = curve.apply(level);
+        self.set_volume(volume, volume)
+    }
+
+    /// Set the volume of both channels in decibels, where 0.0 is full volume and each -20.0
+    /// divides the amplitude by ten (so -60.0 is nearly silent). Values above 0.0 can't make
+    /// the device louder than full volume, and are clamped to it.
+    ///
+    /// Returns `Error::InvalidParam` if the value is NaN or infinite. To silence the device, use
+    /// `set_volume` with 0.0 instead.
+    ///
+    /// ```
+    /// use winaudio::device::{self, WAVE_MAPPER};
+    /// use winaudio::wave::{Format, Out};
+    /// use winaudio::Error;
+    ///
+    /// # // Skip instead of failing where there are no devices at all, not even a null one.
+    /// # if device::count() == 0 {
+    /// #     return;
+    /// # }
+    /// let mut out = Out::open(WAVE_MAPPER, &Format::pcm(1, 8000, 8)).unwrap();
+    /// assert_eq!(out.set_volume_db(f32::NAN), Err(Error::InvalidParam));
+    /// assert_eq!(out.set_volume_db(f32::INFINITY), Err(Error::InvalidParam));
+    /// ```
+    pub fn set_volume_db(&mut self, db: f32) -> Result<(), Error> {
+        if !db.is_finite() {
+            return Err(Error::InvalidParam);
+        }
+        let volume = 10f32.powf(db / 20.0).clamp(0.0, 1.0);
+        self.set_volume(volume, volume)
+    }
+
+    /// Current volume setting in decibels, as `(left, right)`, like `get_volume`. A channel that's
+    /// silent is reported as negative infinity.
+    pub fn get_volume_db(&self) -> Result<(f32, f32), Error> {
+        let (left, right) = self.get_volume()?;
+        Ok((20.0 * left.log10(), 20.0 * right.log10()))
+    }
+
+    /// Change the pitch without changing the playback rate or the sample rate, where 1.0 is
+    /// the original pitch. Only devices that support `Functionality::Pitch` can do this, and
+    /// `Error::NotSupported` is returned otherwise.
+    pub fn set_pitch(&mut self, pitch: f32) -> Result<(), Error> {
+        if !pitch.is_finite() || pitch <= 0.0 {
             return Err(Error::InvalidParam);
         }
-        let left = (left * 0xffff as f32) as u32;
-        let right = (right * 0xffff as f32) as u32;
-        let vol = left | (right << 16);
-        check_multimedia_error(unsafe { waveOutSetVolume(self.hwo, vol) })
+        check_multimedia_error_ctx(
+            unsafe { waveOutSetPitch(self.hwo, to_fixed_16_16(pitch)) },
+            "waveOutSetPitch",
+        )
+    }
+
+    /// Current pitch setting, where 1.0 is the original pitch.
+    pub fn get_pitch(&self) -> Result<f32, Error> {
+        let mut pitch = 0;
+        check_multimedia_error_ctx(
+            unsafe { waveOutGetPitch(self.hwo, &mut pitch) },
+            "waveOutGetPitch",
+        )?;
+        Ok(from_fixed_16_16(pitch))
+    }
+
+    /// Change the playback rate without changing the sample rate, where 1.0 is the normal
+    /// rate, so 2.0 plays twice as fast (and at a higher pitch). Only devices that support
+    /// `Functionality::PlaybackRate` can do this, and `Error::NotSupported` is returned
+    /// otherwise.
+    pub fn set_playback_rate(&mut self, rate: f32) -> Result<(), Error> {
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(Error::InvalidParam);
+        }
+        check_multimedia_error_ctx(
+            unsafe { waveOutSetPlaybackRate(self.hwo, to_fixed_16_16(rate)) },
+            "waveOutSetPlaybackRate",
+        )
+    }
+
+    /// Current playback rate setting, where 1.0 is the normal rate.
+    pub fn get_playback_rate(&self) -> Result<f32, Error> {
+        let mut rate = 0;
+        check_multimedia_error_ctx(
+            unsafe { waveOutGetPlaybackRate(self.hwo, &mut rate) },
+            "waveOutGetPlaybackRate",
+        )?;
+        Ok(from_fixed_16_16(rate))
+    }
+
+    /// Prepares both buffers with `BUFFER_SIZE` bytes each. If there's not enough memory for
+    /// them, the size is halved until it succeeds or goes below `MIN_BUFFER_SIZE`.
+    fn prepare_buffers(
+        hwo: HWAVEOUT,
+        align: usize,
+        pool: Option<&BufferPool>,
+    ) -> Result<[Buffer; 2], Error> {
+        let mut size = Self::BUFFER_SIZE;
+        loop {
+            let buffers = Self::prepare_block(hwo, align, size, pool).and_then(|mut first| {
+                match Self::prepare_block(hwo, align, size, pool) {
+                    Ok(second) => Ok([first, second]),
+                    Err(e) => {
+                        // The first buffer is already prepared, so it has to be unprepared before
+                        // its memory can be reused. If that fails, the device may still use it.
+                        let unprepared =
+                            unsafe { waveOutUnprepareHeader(hwo, &mut first.header, HDR_SIZE) };
+                        match (unprepared, pool) {
+                            (MMSYSERR_NOERROR, Some(pool)) => pool.put(first.buffer),
+                            (MMSYSERR_NOERROR, None) => {}
+                            _ => mem::forget(first.buffer),
+                        }
+                        Err(e)
+                    }
+                }
+            });
+            match buffers {
+                // The error stays in the `ErrorContext`, and the size used can be queried.
+                Err(Error::NoMemory) if size / 2 >= Self::MIN_BUFFER_SIZE => size /= 2,
+                buffers => return buffers,
+            }
+        }
     }
 
     /// Prepares a waveform-audio data block for playback. Data can be read
     /// into the block before sending the block for playback to `write()`.
-    fn prepare_block(hwo: HWAVEOUT, align: usize, mut size: usize) -> Result<Buffer, Error> {
-        if size % align != 0 {
-            size += align - (size % align);
-        }
-
-        let mut buffer = vec![0; size].into_boxed_slice();
-        let mut header = WAVEHDR {
-            lpData: buffer.as_mut_ptr() as LPSTR,
-            dwBufferLength: buffer.len() as u32,
-            dwBytesRecorded: 0,
-            dwUser: 0,
-            dwFlags: 0,
-            dwLoops: 0,
-            lpNext: ptr::null_mut(),
-            reserved: 0,
-        };
-        check_multimedia_error(unsafe { waveOutPrepareHeader(hwo, &mut header, HDR_SIZE) })?;
+    fn prepare_block(
+        hwo: HWAVEOUT,
+        align: usize,
+        size: usize,
+        pool: Option<&BufferPool>,
+    ) -> Result<Buffer, Error> {
+        // The length of the buffer is given to the device as a `u32`, so it must fit in one.
+        let size = size
+            .checked_next_multiple_of(align.max(1))
+            .filter(|&size| size <= u32::MAX as usize)
+            .ok_or(Error::InvalidParam)?;
+
+        let buffer = pool
+            .and_then(|pool| pool.take(size))
+            .unwrap_or_else(|| vec![0; size].into_boxed_slice());
+        debug_assert!(buffer.len() <= u32::MAX as usize);
+        let mut buffer = Buffer::new(buffer);
+        if let Err(e) = check_multimedia_error_ctx(
+            unsafe { waveOutPrepareHeader(hwo, &mut buffer.header, HDR_SIZE) },
+            "waveOutPrepareHeader",
+        ) {
+            if let Some(pool) = pool {
+                pool.put(buffer.buffer);
+            }
+            return Err(e);
+        }
 
-        if header.dwFlags & WHDR_PREPARED == 0 {
-            return Err(Error::InvalidFlag);
+        // The call succeeding is what matters, but some drivers don't set the flag right away.
+        // It's set manually so that the buffer is still unprepared when it's no longer needed.
+        if buffer.header.dwFlags & WHDR_PREPARED == 0 {
+            record_error_context(Error::InvalidFlag, "waveOutPrepareHeader");
+            buffer.header.dwFlags |= WHDR_PREPARED;
         }
-        Ok(Buffer { header, buffer })
+        Ok(buffer)
+    }
+
+    /// Register a function to be called every time the device is done playing a buffer, with the
+    /// index of that buffer. This can be used to refill buffers as soon as they're free, or to
+    /// keep track of the playback progress. Any previously registered function is replaced.
+    ///
+    /// The function is called from the callback thread of the device, not from the thread that
+    /// writes the buffers, so it must be `Send`. The system does not allow the callback to call
+    /// waveform-audio functions, so it must not use this `Out` in any way (doing so may deadlock),
+    /// and it should return quickly, as playback may be delayed otherwise. Buffers marked as done
+    /// by `stop`, including when the `Out` is dropped, also call the function.
+    ///
+    /// A panic in the function can't unwind into the system, so it's discarded, and the function
+    /// keeps being called for the next buffers.
+    pub fn set_on_buffer_done(&mut self, on_buffer_done: Box<dyn FnMut(usize) + Send>) {
+        // A panic in the previous function poisons the lock, but it's replaced all the same.
+        *self
+            .shared
+            .on_buffer_done
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(on_buffer_done);
     }
 
     /// Get a mutable reference to the buffers so that they can be read into.
+    ///
+    /// Prefer `buffer_mut` and `buffer_count`, which don't depend on there being two buffers.
     pub fn buffers(&mut self) -> &mut [Buffer; 2] {
         &mut self.buffers
     }
 
+    /// Get a mutable reference to the buffer at the given index so that it can be read into, or
+    /// `None` if the index is not less than `buffer_count`.
+    pub fn buffer_mut(&mut self, index: usize) -> Option<&mut Buffer> {
+        self.buffers.get_mut(index)
+    }
+
+    /// Number of buffers the device alternates between.
+    pub fn buffer_count(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// Size of each of the buffers, which is the most data that can be written to the device at
+    /// once. It's smaller than usual if there wasn't enough memory when the device was opened.
+    ///
+    /// ```
+    /// use winaudio::device::{self, WAVE_MAPPER};
+    /// use winaudio::wave::{Format, Out};
+    ///
+    /// # // Skip instead of failing where there are no devices at all, not even a null one.
+    /// # if device::count() == 0 {
+    /// #     return;
+    /// # }
+    /// let mut out = Out::open(WAVE_MAPPER, &Format::pcm(2, 44100, 16)).unwrap();
+    /// assert!(out.buffer_size() >= 16 * 1024);
+    /// assert_eq!(out.buffer_size(), out.buffer_mut(0).unwrap().capacity());
+    /// ```
+    pub fn buffer_size(&self) -> usize {
+        self.buffers[0].capacity()
+    }
+
     /// Write the data block from the first buffer to the waveform-audio output device.
     ///
-    /// Note that this has to `wait` until the previous buffer completes, and will lock
-    /// indefinitely if the playback is paused.
+    /// Note that this has to `wait` until the previous buffer completes. If the playback is
+    /// paused and a buffer is still queued, `Error::HandleBusy` is returned instead.
+    ///
+    /// If the device reports that the buffer is still playing with `Error::StillPlaying`, the
+    /// write is retried once after waiting for it, and the error is only returned if it recurs.
     pub fn write_first(&mut self) -> Result<(), Error> {
-        self.wait();
-        self.cb_done.clear();
-        check_multimedia_error(unsafe {
-            waveOutWrite(self.hwo, &mut self.buffers[0].header, HDR_SIZE)
-        })
+        self.wait_for_write(|out| out.buffers.iter().any(is_queued))?;
+        self.write_buffer(0)
     }
 
     /// Write the data block from the second buffer to the waveform-audio output device.
     ///
-    /// Note that this has to `wait` until the previous buffer completes, and will lock
-    /// indefinitely if the playback is paused.
+    /// Note that this has to `wait` until the previous buffer completes. If the playback is
+    /// paused and a buffer is still queued, `Error::HandleBusy` is returned instead.
+    ///
+    /// If the device reports that the buffer is still playing with `Error::StillPlaying`, the
+    /// write is retried once after waiting for it, and the error is only returned if it recurs.
+    ///
+    /// ```
+    /// use winaudio::device::{self, WAVE_MAPPER};
+    /// use winaudio::wave::{Format, Out};
+    ///
+    /// # // Skip instead of failing where there are no devices at all, not even a null one.
+    /// # if device::count() == 0 {
+    /// #     return;
+    /// # }
+    /// let mut out = Out::open(WAVE_MAPPER, &Format::pcm(1, 8000, 8)).unwrap();
+    /// for index in 0..2 {
+    ///     let buffer = out.buffer_mut(index).unwrap();
+    ///     buffer.as_mut_slice()[..8].copy_from_slice(&[128; 8]);
+    ///     buffer.set_valid_len(8).unwrap();
+    /// }
+    ///
+    /// // Buffers this short are reused right as the device is done with them.
+    /// for _ in 0..100 {
+    ///     out.write_first().unwrap();
+    ///     out.write_second().unwrap();
+    /// }
+    /// out.wait();
+    /// assert_eq!(out.queued_buffers(), 0);
+    /// ```
     pub fn write_second(&mut self) -> Result<(), Error> {
-        self.wait();
-        self.cb_done.clear();
-        check_multimedia_error(unsafe {
-            waveOutWrite(self.hwo, &mut self.buffers[1].header, HDR_SIZE)
-        })
+        self.wait_for_write(|out| out.buffers.iter().any(is_queued))?;
+        self.write_buffer(1)
+    }
+
+    /// Write floating point samples in the `-1.0..=1.0` range to the output device, waiting for
+    /// the previous buffers to complete as needed. Samples from all channels must be interleaved.
+    /// If the playback is paused and it would have to wait, `Error::HandleBusy` is returned.
+    /// Writes are retried once on `Error::StillPlaying`, as with `write_first`.
+    ///
+    /// If the device was opened with `Tag::IeeeFloat`, the samples are written as-is. If it was
+    /// opened with 16-bit `Tag::Pcm`, they are clipped and converted to `i16` first, as done by
+    /// `convert::f32_to_i16`. Any other format results in `Error::BadFormat`.
+    ///
+    /// ```no_run
+    /// use winaudio::device::WAVE_MAPPER;
+    /// use winaudio::wave::{Format, Out};
+    ///
+    /// let fmt = Format::ieee_float(1, 44100);
+    /// let mut out = Out::open(WAVE_MAPPER, &fmt).unwrap();
+    ///
+    /// let sine = (0..44100)
+    ///     .map(|i| (i as f32 * 440.0 * 2.0 * std::f32::consts::PI / 44100.0).sin())
+    ///     .collect::<Vec<f32>>();
+    ///
+    /// out.write_f32(&sine).unwrap();
+    /// out.wait();
+    /// ```
+    pub fn write_f32(&mut self, samples: &[f32]) -> Result<(), Error> {
+        let data = match (self.fmt.format_tag, self.fmt.bits_per_sample) {
+            (Tag::IeeeFloat, 32) => samples.iter().flat_map(|s| s.to_le_bytes()).collect(),
+            (Tag::Pcm, 16) => f32_to_i16(samples)
+                .into_iter()
+                .flat_map(i16::to_le_bytes)
+                .collect::<Vec<u8>>(),
+            _ => return Err(Error::BadFormat),
+        };
+
+        self.write_chunked(&data)
+    }
+
+    /// Write samples of the type that matches the format the device was opened with, waiting
+    /// for the previous buffers to complete as needed. Returns `Error::BadFormat` without
+    /// writing anything if the format tag or bits per sample of the samples and the device
+    /// differ, such as when writing `i16` samples to a device opened for 8-bit data.
+    ///
+    /// ```no_run
+    /// use winaudio::device::WAVE_MAPPER;
+    /// use winaudio::wave::{Format, Out};
+    /// use winaudio::Error;
+    ///
+    /// let mut out = Out::open(WAVE_MAPPER, &Format::pcm(1, 8000, 8)).unwrap();
+    /// assert_eq!(out.write_typed(&[0i16; 8000]), Err(Error::BadFormat));
+    /// out.write_typed(&[128u8; 8000]).unwrap();
+    /// ```
+    pub fn write_typed<S: Sample>(&mut self, samples: &[S]) -> Result<(), Error> {
+        if self.fmt.format_tag != S::FORMAT_TAG || self.fmt.bits_per_sample != S::BITS_PER_SAMPLE {
+            return Err(Error::BadFormat);
+        }
+
+        let mut data = Vec::with_capacity(samples.len() * (S::BITS_PER_SAMPLE / 8) as usize);
+        samples.iter().for_each(|s| s.extend_le_bytes(&mut data));
+        self.write_chunked(&data)
+    }
+
+    /// Write silence lasting for the given duration, rounded down to a whole number of blocks,
+    /// waiting for the previous buffers to complete as needed. This can be used to insert gaps
+    /// between sounds or to start with some pre-roll.
+    pub fn write_silence(&mut self, duration: Duration) -> Result<(), Error> {
+        // 8-bit PCM samples are unsigned, so their silence is the midpoint rather than zero.
+        let silence = match (self.fmt.format_tag, self.fmt.bits_per_sample) {
+            (Tag::Pcm, 8) => 0x80,
+            _ => 0,
+        };
+
+        // The silence is generated one buffer at a time rather than all at once. The chunks are
+        // split over the buffers like any other write, and stay whole blocks as long as each one
+        // is a whole number of blocks.
+        let align = self.fmt.bytes_per_frame().max(1) as u64;
+        let size = self.buffer_size() as u64;
+        let max_chunk = (size - size % align).max(align);
+
+        let mut remaining = self.fmt.duration_to_bytes(duration);
+        let chunk = vec![silence; remaining.min(max_chunk) as usize];
+        while remaining != 0 {
+            let len = remaining.min(chunk.len() as u64);
+            self.write_chunked(&chunk[..len as usize])?;
+            remaining -= len;
+        }
+        Ok(())
+    }
+
+    /// Play all of the in-memory data and wait for the device to finish playing it.
+    ///
+    /// The data is split into chunks as large as a buffer, which are whole blocks, and each
+    /// buffer is refilled as soon as the device is done with it, so the device is kept busy with
+    /// every buffer queued. Any data left pending from previous writes is played first.
+    ///
+    /// Returns `Error::HandleBusy` if the playback is paused before all the data is written.
+    ///
+    /// ```
+    /// use std::time::{Duration, Instant};
+    /// use winaudio::device::{self, WAVE_MAPPER};
+    /// use winaudio::wave::{Format, Out};
+    ///
+    /// # // Skip instead of failing where there are no devices at all, not even a null one.
+    /// # if device::count() == 0 {
+    /// #     return;
+    /// # }
+    /// let fmt = Format::pcm(2, 44100, 16);
+    /// let mut out = Out::open(WAVE_MAPPER, &fmt).unwrap();
+    ///
+    /// // Enough data to go through every buffer more than once.
+    /// let len = out.buffer_mut(0).unwrap().capacity() * (out.buffer_count() + 1);
+    /// let start = Instant::now();
+    /// out.play_slice(&vec![0; len]).unwrap();
+    ///
+    /// // The device never ran out of data, so it took about as long as the data lasts.
+    /// assert_eq!(out.underrun_count(), 0);
+    /// assert!(start.elapsed() < fmt.bytes_to_duration(len as u64) + Duration::from_millis(500));
+    /// ```
+    pub fn play_slice(&mut self, pcm: &[u8]) -> Result<(), Error> {
+        self.write_pending()?;
+
+        for chunk in pcm.chunks(self.buffers[self.next].capacity()) {
+            self.write_next(chunk)?;
+        }
+        self.wait_unpaused(|out| out.buffers.iter().any(is_queued))
+    }
+
+    /// Open the `device::WAVE_MAPPER` and play the data produced by `fill` until it's done.
+    ///
+    /// Every time a buffer is free, `fill` is called to write data into it, and must return how
+    /// many bytes it wrote. Those bytes are played, and returning 0 finishes the playback, after
+    /// waiting for the device to play everything. If the bytes written don't make up a whole
+    /// number of blocks, the last block is completed with silence.
+    ///
+    /// ```no_run
+    /// use winaudio::wave::{Format, Out};
+    ///
+    /// let fmt = Format::pcm(1, 8000, 8);
+    /// let mut remaining = 8000;
+    /// Out::play_with(&fmt, |buf| {
+    ///     // One second of a square wave.
+    ///     let len = buf.len().min(remaining);
+    ///     for (i, sample) in buf[..len].iter_mut().enumerate() {
+    ///         *sample = if (i / 20) % 2 == 0 { 96 } else { 160 };
+    ///     }
+    ///     remaining -= len;
+    ///     len
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn play_with<F: FnMut(&mut [u8]) -> usize>(fmt: &Format, mut fill: F) -> Result<(), Error> {
+        let mut out = Self::open(device::WAVE_MAPPER, fmt)?;
+        let align = fmt.block_align.max(1) as usize;
+        loop {
+            let next = out.next;
+            out.wait_unpaused(|out| is_queued(&out.buffers[next]))?;
+
+            let buffer = &mut out.buffers[next].buffer;
+            let len = fill(buffer).min(buffer.len());
+            if len == 0 {
+                break;
+            }
+            let padded = len.div_ceil(align) * align;
+            buffer[len..padded].iter_mut().for_each(|b| *b = 0);
+
+            out.pending = padded;
+            out.write_pending()?;
+        }
+        out.wait();
+        Ok(())
+    }
+
+    /// Write all of the data to the output device, splitting it over as many buffers as needed
+    /// and waiting for the previous buffers to complete. Data larger than `Buffer::capacity` is
+    /// never truncated, as it's split with `split_into_buffers`.
+    pub(crate) fn write_chunked(&mut self, data: &[u8]) -> Result<(), Error> {
+        for chunk in split_into_buffers(data, self.pending, self.buffer_size()) {
+            self.write_next(chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Copy as much data as possible into the next buffer and write it to the output device,
+    /// after waiting for the device to be done with that buffer. Returns how many bytes were
+    /// written.
+    fn write_next(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let len = self.fill_next(data)?;
+        self.write_pending()?;
+        Ok(len)
+    }
+
+    /// Copy as much data as possible after the pending bytes of the next buffer, without
+    /// writing it to the output device. Returns how many bytes were copied.
+    fn fill_next(&mut self, data: &[u8]) -> Result<usize, Error> {
+        if self.pending == 0 {
+            // The device may still be playing the buffer from a previous write.
+            let next = self.next;
+            self.wait_for_write(|out| is_queued(&out.buffers[next]))?;
+        }
+        let buffer = &mut self.buffers[self.next].buffer[self.pending..];
+        let len = data.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&data[..len]);
+        self.pending += len;
+        Ok(len)
+    }
+
+    /// Write the data into the next buffer and send it to the output device only if the device
+    /// is done playing that buffer, without waiting. Returns `Ok(false)` if all buffers are still
+    /// queued, in which case nothing is written.
+    ///
+    /// Unlike the other write methods, this never waits, so the data can't be split over more
+    /// than one buffer. If it doesn't fit in what's left of the next buffer (see
+    /// `Buffer::capacity`), `Error::InvalidParam` is returned and nothing is written.
+    ///
+    /// ```
+    /// use winaudio::device::{self, WAVE_MAPPER};
+    /// use winaudio::wave::{Format, Out};
+    /// use winaudio::Error;
+    ///
+    /// # // Skip instead of failing where there are no devices at all, not even a null one.
+    /// # if device::count() == 0 {
+    /// #     return;
+    /// # }
+    /// let mut out = Out::open(WAVE_MAPPER, &Format::pcm(1, 8000, 8)).unwrap();
+    /// let too_long = vec![128; out.buffer_mut(0).unwrap().capacity() + 1];
+    /// assert_eq!(out.try_write_next(&too_long), Err(Error::InvalidParam));
+    /// assert_eq!(out.try_write_next(&too_long[1..]), Ok(true));
+    /// ```
+    pub fn try_write_next(&mut self, data: &[u8]) -> Result<bool, Error> {
+        if is_queued(&self.buffers[self.next]) {
+            return Ok(false);
+        }
+        if data.len() > self.buffers[self.next].capacity() - self.pending {
+            return Err(Error::InvalidParam);
+        }
+        self.fill_next(data)?;
+        self.write_pending()?;
+        Ok(true)
+    }
+
+    /// Write the pending bytes of the next buffer to the output device. The buffer was already
+    /// free when they were copied into it, so this doesn't wait for the other buffers, which keep
+    /// playing in the meantime. Does nothing if there are no pending bytes.
+    fn write_pending(&mut self) -> Result<(), Error> {
+        if self.pending == 0 {
+            return Ok(());
+        }
+
+        self.buffers[self.next].header.dwBufferLength = self.pending as u32;
+        self.write_buffer(self.next)?;
+        self.next = (self.next + 1) % self.buffers.len();
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// Send the buffer at the given index to the output device.
+    ///
+    /// If the device still has the buffer queued from a previous write, it waits for that
+    /// buffer specifically, as writing a queued buffer again fails with `Error::StillPlaying`.
+    /// The flags may lag behind the device, so if it still fails that way, it waits and retries
+    /// once more.
+    fn write_buffer(&mut self, index: usize) -> Result<(), Error> {
+        if !self.playing {
+            // If the position is not available, `samples_played` will fail anyway.
+            self.baseline = self.position().unwrap_or(0);
+        }
+
+        self.wait_unpaused(|out| is_queued(&out.buffers[index]))?;
+        self.check_underrun();
+
+        let mut retried = false;
+        loop {
+            match check_multimedia_error_ctx(
+                unsafe { waveOutWrite(self.hwo, &mut self.buffers[index].header, HDR_SIZE) },
+                "waveOutWrite",
+            ) {
+                Err(Error::StillPlaying) if !retried => {
+                    retried = true;
+                    self.wait_unpaused(|out| is_queued(&out.buffers[index]))?;
+                }
+                result => break result?,
+            }
+        }
+        self.playing = true;
+        Ok(())
+    }
+
+    /// Count an underrun if the device already played everything it had by the time a buffer
+    /// is about to be sent to it, while it was expected to be playing.
+    fn check_underrun(&mut self) {
+        if self.playing && !self.buffers.iter().any(is_queued) {
+            self.underruns += 1;
+        }
+    }
+
+    /// Number of times the device ran out of data to play before the next buffer was sent to it,
+    /// which causes an audible gap. A high count means the data is not being produced fast enough.
+    ///
+    /// Writing after a `stop` or `flush` does not count as an underrun, but writing after a
+    /// direct `wait` does. So does every `write_first` and `write_second` while playing, as
+    /// they wait for all buffers to complete before writing.
+    pub fn underrun_count(&self) -> u64 {
+        self.underruns
+    }
+
+    /// How long the last write was blocked waiting for the device to be done with a buffer.
+    ///
+    /// Waits close to zero mean the data is barely being produced in time, and an underrun is
+    /// likely, while long waits mean the buffers hold more data than needed.
+    pub fn last_wait_duration(&self) -> Duration {
+        self.last_wait
+    }
+
+    /// Moving average of `last_wait_duration` over the recent writes, with each new write
+    /// weighing an eighth of the total.
+    pub fn average_wait_duration(&self) -> Duration {
+        self.average_wait
+    }
+
+    /// Number of samples (one per channel at a time) played since the current playback started,
+    /// which is either the first write, or the first write after a `stop` or `flush`.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use winaudio::device::{self, WAVE_MAPPER};
+    /// use winaudio::wave::{Format, Out};
+    ///
+    /// # // Skip instead of failing where there are no devices at all, not even a null one.
+    /// # if device::count() == 0 {
+    /// #     return;
+    /// # }
+    /// let mut out = Out::open(WAVE_MAPPER, &Format::pcm(1, 8000, 8)).unwrap();
+    /// out.write_silence(Duration::from_millis(100)).unwrap();
+    /// out.wait();
+    /// assert!(out.samples_played().unwrap() > 0);
+    /// ```
+    pub fn samples_played(&self) -> Result<u64, Error> {
+        // The position is a 32-bit counter which may wrap around during long playbacks.
+        Ok(self.position()?.wrapping_sub(self.baseline) as u64)
+    }
+
+    /// Current position of the device in samples, counting since it was opened or last stopped.
+    fn position(&self) -> Result<u32, Error> {
+        let mut time: MMTIME = unsafe { mem::zeroed() };
+        time.wType = TIME_SAMPLES;
+        check_multimedia_error_ctx(
+            unsafe { waveOutGetPosition(self.hwo, &mut time, mem::size_of::<MMTIME>() as u32) },
+            "waveOutGetPosition",
+        )?;
+
+        // The structure is packed, so the value is read without creating a reference to it.
+        let value = unsafe { ptr::read_unaligned(ptr::addr_of!(time.u) as *const u32) };
+        match time.wType {
+            TIME_SAMPLES => Ok(value),
+            // The device may not support the requested format, and use another one instead.
+            TIME_BYTES => Ok(value / self.fmt.bytes_per_frame().max(1)),
+            _ => Err(Error::NotSupported),
+        }
+    }
+
+    /// Number of buffers that have been written to the device and are not done playing yet.
+    /// Along with `underrun_count`, this helps find out whether the device is being kept busy.
+    pub fn queued_buffers(&self) -> usize {
+        self.buffers.iter().filter(|b| is_queued(b)).count()
     }
 
     /// Wait for the device to finish playing the last chunk of data written.
+    ///
+    /// Note that this will lock indefinitely if the playback is paused.
     pub fn wait(&self) {
-        self.cb_done.wait();
+        self.wait_while(|out| out.buffers.iter().any(is_queued));
+    }
+
+    /// Like `wait`, but without blocking the thread, so that it can be used from asynchronous
+    /// code. The returned future completes once the device finishes playing the last chunk of
+    /// data written.
+    ///
+    /// The future will never complete if the playback is paused.
+    #[cfg(feature = "async")]
+    pub fn drain_async(&mut self) -> impl Future<Output = ()> + '_ {
+        Drain { out: self }
+    }
+
+    /// Like `wait_while`, but fail with `Error::HandleBusy` instead of waiting if the playback
+    /// is paused, as the buffers would never complete.
+    fn wait_unpaused<F: Fn(&Self) -> bool>(&self, busy: F) -> Result<(), Error> {
+        if self.paused && busy(self) {
+            return Err(Error::HandleBusy);
+        }
+        self.wait_while(busy);
+        Ok(())
+    }
+
+    /// Like `wait_unpaused`, but record how long it took for `last_wait_duration`.
+    fn wait_for_write<F: Fn(&Self) -> bool>(&mut self, busy: F) -> Result<(), Error> {
+        let start = Instant::now();
+        self.wait_unpaused(busy)?;
+        self.last_wait = start.elapsed();
+        self.average_wait = (self.average_wait * 7 + self.last_wait) / 8;
+        Ok(())
+    }
+
+    /// Wait for buffers to complete for as long as the condition holds.
+    fn wait_while<F: Fn(&Self) -> bool>(&self, busy: F) {
+        // More than one buffer may be queued, and any of them can signal the event,
+        // so rely on the flags of each buffer to know when the right ones are done.
+        loop {
+            self.shared.done.clear();
+            if !busy(self) {
+                break;
+            }
+            self.shared.done.wait();
+        }
     }
 
     /// Pauses playback on the output device. The current position is saved.
@@ -156,7 +1098,9 @@ impl Out {
     /// Calling this function when the output is already paused has no effect,
     /// and the function returns `Ok`.
     pub fn pause(&mut self) -> Result<(), Error> {
-        check_multimedia_error(unsafe { waveOutPause(self.hwo) })
+        check_multimedia_error_ctx(unsafe { waveOutPause(self.hwo) }, "waveOutPause")?;
+        self.paused = true;
+        Ok(())
     }
 
     /// Resume playback on the paused output device.
@@ -164,37 +1108,292 @@ impl Out {
     /// Calling this function when the output is not paused has no effect, and the function
     /// returns `Ok`.
     pub fn resume(&mut self) -> Result<(), Error> {
-        check_multimedia_error(unsafe { waveOutRestart(self.hwo) })
+        check_multimedia_error_ctx(unsafe { waveOutRestart(self.hwo) }, "waveOutRestart")?;
+        self.paused = false;
+        Ok(())
+    }
+
+    /// Whether the playback is currently paused with `pause`.
+    pub fn is_paused(&self) -> bool {
+        self.paused
     }
 
     /// Stops playback on the output device and resets the current position to zero. All
-    /// pending playback buffers are marked as done.
+    /// pending playback buffers are marked as done. If the playback was paused, it's no longer
+    /// paused afterwards, so that the next write starts playing right away.
     pub fn stop(&mut self) -> Result<(), Error> {
-        check_multimedia_error(unsafe { waveOutReset(self.hwo) })
+        self.playing = false;
+        self.baseline = 0;
+        check_multimedia_error_ctx(unsafe { waveOutReset(self.hwo) }, "waveOutReset")?;
+        // Resetting the device doesn't take it out of the paused state.
+        if self.paused {
+            self.resume()?;
+        }
+        Ok(())
     }
+
+    /// Let the device finish playing everything written so far on a new thread, and close it
+    /// once it's done, without blocking the caller. Data still pending in a partially-filled
+    /// buffer is written first. The returned handle can be joined to get the result of `close`.
+    ///
+    /// If the device is paused, it can't finish, so it's closed right away and the thread
+    /// returns `Error::HandleBusy`.
+    ///
+    /// ```no_run
+    /// use winaudio::device::WAVE_MAPPER;
+    /// use winaudio::wave::{Format, Out};
+    ///
+    /// let mut out = Out::open(WAVE_MAPPER, &Format::pcm(1, 8000, 8)).unwrap();
+    /// out.write_typed(&[128u8; 8000]).unwrap();
+    /// out.detach(); // keeps playing the whole second
+    /// ```
+    pub fn detach(mut self) -> thread::JoinHandle<Result<(), Error>> {
+        thread::spawn(move || {
+            let drained = self
+                .write_pending()
+                .and_then(|_| self.wait_unpaused(|out| out.buffers.iter().any(is_queued)));
+            let closed = self.close();
+            drained.and(closed)
+        })
+    }
+
+    /// Stop playback, release the buffers and close the device, returning the first error that
+    /// occurred. Unlike dropping the `Out`, this lets the caller know whether it succeeded.
+    ///
+    /// Buffers that the device may still be using can't be freed, so they are leaked instead.
+    pub fn close(mut self) -> Result<(), Error> {
+        self.closed = true;
+        if let Err(e) = self.stop() {
+            self.buffers
+                .iter_mut()
+                .for_each(|b| mem::forget(mem::take(&mut b.buffer)));
+            return Err(e);
+        }
+
+        let hwo = self.hwo;
+        let mut result = Ok(());
+        for b in self.buffers.iter_mut() {
+            if b.header.dwFlags & WHDR_PREPARED != 0 {
+                let unprepared = check_multimedia_error_ctx(
+                    unsafe { waveOutUnprepareHeader(hwo, &mut b.header, HDR_SIZE) },
+                    "waveOutUnprepareHeader",
+                );
+                match (&unprepared, &self.pool) {
+                    (Err(_), _) => mem::forget(mem::take(&mut b.buffer)),
+                    (Ok(()), Some(pool)) => pool.put(mem::take(&mut b.buffer)),
+                    (Ok(()), None) => {}
+                }
+                result = result.and(unprepared);
+            }
+        }
+
+        result.and(check_multimedia_error_ctx(
+            unsafe { waveOutClose(hwo) },
+            "waveOutClose",
+        ))
+    }
+}
+
+/// Playback operations of a wave output device. It's implemented by `Out`, and code built on
+/// top of it can be made generic over this trait, so that it can also be tested without a real
+/// device (for example, by using `testing::MockOut` with the `testing` feature).
+pub trait WaveOut {
+    /// Write all of the data to the device, waiting for it as needed.
+    fn write_samples(&mut self, data: &[u8]) -> Result<(), Error>;
+
+    /// New volume setting. A value of 1.0 represents full volume, and 0.0 silence.
+    fn set_volume(&mut self, left: f32, right: f32) -> Result<(), Error>;
+
+    /// Pauses playback. The current position is saved.
+    fn pause(&mut self) -> Result<(), Error>;
+
+    /// Resume paused playback.
+    fn resume(&mut self) -> Result<(), Error>;
+
+    /// Stops playback and resets the current position to zero.
+    fn stop(&mut self) -> Result<(), Error>;
+
+    /// Wait for the device to finish playing everything written so far.
+    fn wait(&self);
+}
+
+impl WaveOut for Out {
+    fn write_samples(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.write_chunked(data)
+    }
+
+    fn set_volume(&mut self, left: f32, right: f32) -> Result<(), Error> {
+        Out::set_volume(self, left, right)
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        Out::pause(self)
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        Out::resume(self)
+    }
+
+    fn stop(&mut self) -> Result<(), Error> {
+        Out::stop(self)
+    }
+
+    fn wait(&self) {
+        Out::wait(self)
+    }
+}
+
+// The handle can be used from any thread, and the state shared with the callback is synchronized.
+unsafe impl Send for Out {}
+
+/// Bytes are copied into the next free buffer, which is written to the output device once full.
+/// Flushing writes any partially-filled buffer and waits for the device to play everything.
+///
+/// ```no_run
+/// use std::fs::File;
+/// use std::io::{self, Seek, SeekFrom, Write};
+/// use winaudio::device::WAVE_MAPPER;
+/// use winaudio::wave::{Format, Out};
+///
+/// let mut file = File::open("test.wav").unwrap();
+/// let fmt = Format::from_wav_stream(&mut file).unwrap();
+/// file.seek(SeekFrom::Start(44)).unwrap();
+///
+/// let mut out = Out::open(WAVE_MAPPER, &fmt).unwrap();
+/// io::copy(&mut file, &mut out).unwrap();
+/// out.flush().unwrap();
+/// ```
+impl io::Write for Out {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.fill_next(buf)?;
+        if self.pending == self.buffers[self.next].buffer.len() {
+            self.write_pending()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_pending()?;
+        self.wait_unpaused(|out| out.buffers.iter().any(is_queued))?;
+        self.playing = false;
+        Ok(())
+    }
+}
+
+/// Future returned by `Out::drain_async`.
+#[cfg(feature = "async")]
+struct Drain<'a> {
+    out: &'a Out,
+}
+
+#[cfg(feature = "async")]
+impl Future for Drain<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // Register the waker before checking, so that a buffer completing in between
+        // is not missed.
+        *self
+            .out
+            .shared
+            .waker
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(cx.waker().clone());
+        if self.out.buffers.iter().any(is_queued) {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+}
+
+/// Error returned by `Out::try_open`, which tells a format that's wrong in itself apart from one
+/// the device failed to open.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpenError {
+    /// The format failed `Format::validate`, so it was never given to the device.
+    Format(FormatError),
+    /// The device failed to open.
+    Device(Error),
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Format(e) => e.fmt(f),
+            Self::Device(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for OpenError {}
+
+impl From<Error> for OpenError {
+    fn from(error: Error) -> Self {
+        Self::Device(error)
+    }
+}
+
+/// Invalid formats become `Error::BadFormat`, which is what the device would have returned.
+impl From<OpenError> for Error {
+    fn from(error: OpenError) -> Self {
+        match error {
+            OpenError::Format(_) => Error::BadFormat,
+            OpenError::Device(e) => e,
+        }
+    }
+}
+
+/// Invalid formats keep what's wrong with them as the message of an `InvalidData` error.
+impl From<OpenError> for io::Error {
+    fn from(error: OpenError) -> Self {
+        match error {
+            OpenError::Format(e) => io::Error::new(io::ErrorKind::InvalidData, e),
+            OpenError::Device(e) => e.into(),
+        }
+    }
+}
+
+/// Whether the buffer has been written to the device and it's not done playing it yet.
+fn is_queued(buffer: &Buffer) -> bool {
+    // The flags are updated by the driver, possibly from a different thread. The header is
+    // packed, so the field is read as bytes to avoid creating an unaligned reference.
+    let flags =
+        unsafe { ptr::read_volatile(ptr::addr_of!(buffer.header.dwFlags) as *const [u8; 4]) };
+    u32::from_ne_bytes(flags) & WHDR_INQUEUE != 0
 }
 
 impl Drop for Out {
     fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+
         // TODO leak buffers instead of panicking
         self.stop().expect("failed to stop playback prior to drop");
 
         let hwo = self.hwo;
+        let pool = self.pool.take();
 
         // Can't do this in the buffers' drop because we own them and would be
         // dropped after dropping self (when the device handle is already closed).
         self.buffers.iter_mut().for_each(|b| {
             if b.header.dwFlags & WHDR_PREPARED != 0 {
-                match check_multimedia_error(unsafe {
-                    waveOutUnprepareHeader(hwo, &mut b.header, HDR_SIZE)
-                }) {
-                    Ok(_) => {}
+                match check_multimedia_error_ctx(
+                    unsafe { waveOutUnprepareHeader(hwo, &mut b.header, HDR_SIZE) },
+                    "waveOutUnprepareHeader",
+                ) {
+                    Ok(_) => {
+                        if let Some(pool) = &pool {
+                            pool.put(mem::take(&mut b.buffer));
+                        }
+                    }
                     Err(e) => eprintln!("error during unprepare header: {:?}", e),
                 }
             }
         });
 
-        match check_multimedia_error(unsafe { waveOutClose(hwo) }) {
+        match check_multimedia_error_ctx(unsafe { waveOutClose(hwo) }, "waveOutClose") {
             Ok(_) => {}
             Err(e) => {
                 eprintln!("error dropping wave out handle: {:?}", e);
@@ -1,81 +1,1111 @@
-use crate::device::WAVE_MAPPER;
-use crate::util::BinaryRead as _;
-use crate::wave::{Format, Out};
-use std::fs::File;
-use std::io::{self, Read, Seek, SeekFrom};
+use crate::device::{self, WAVE_MAPPER};
+use crate::util::read_chunk;
+use crate::wave::control::{Command, PlaybackHandle};
+use crate::wave::format::Tag;
+use crate::wave::reader::{self, read_format, read_riff_header, read_u32};
+use crate::wave::resample::Resampler;
+#[cfg(feature = "decode")]
+use crate::wave::Decoder;
+use crate::wave::{BroadcastExtension, Buffer, Format, Out, WavReader, WaveOut};
+use crate::Error;
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
 use std::path::Path;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// How often to check for commands while waiting for the device to finish playing.
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
 /// Helper to play `.wav` files.
+///
+/// With the `decode` feature, other files which can't be played directly (such as MP3, FLAC or
+/// Ogg Vorbis, and `.wav` files that are not PCM or IEEE float) are decoded to PCM on the fly.
 pub struct Player {
     fmt: Format,
-    file: File,
+    source: Source,
+    data_len: u64,
+    // Number of samples as stored in the `fact` chunk, present for compressed formats, or as
+    // reported by the decoder.
+    sample_length: Option<u64>,
+    // Metadata from the `bext` chunk of Broadcast Wave Format files.
+    bext: Option<BroadcastExtension>,
+    // Whether the file is a `RIFX` file, with big-endian samples that need to be byte-swapped.
+    big_endian: bool,
+    // The device used by the last `play`, which keeps playing the end of the data after it
+    // returns, and the volume to use for it.
+    out: Option<Out>,
+    volume: (f32, f32),
+    // Whether to resample the data if the device doesn't support its sample rate.
+    resample: bool,
+    // Whether to open the device again if it stops working while spawned.
+    reopen: bool,
+    // How fast to play, and how much of it is done by resampling because the device can't
+    // change its playback rate (or 1.0 if it can).
+    speed: f32,
+    resampled_speed: f32,
+    // Loudest sample in the last buffer written by `play`.
+    peak: f32,
+}
+
+/// Reader over the contents of a file, which can be sent to other threads along with the `Player`.
+trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// Where the waveform-audio data of a `Player` comes from.
+enum Source {
+    /// The data is stored as-is in the `data` chunk of a `.wav` file.
+    Wav(WavReader<Box<dyn ReadSeek>>),
+    /// The data is read as-is from a stream that can't seek, so it can only be played once.
+    Stream(Option<Box<dyn Read + Send>>),
+    /// The data is decoded from the file as it's read.
+    #[cfg(feature = "decode")]
+    Decoded(Box<Decoder>),
 }
 
 impl Player {
     /// Create a new `Player` instance from a `.wav` file stored in disk.
     pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let mut file = File::open(path)?;
+        Self::from_reader(File::open(path)?)
+    }
 
-        let fmt = Format::from_wav_stream(&mut file)?;
+    /// Create a new `Player` instance from a `.wav` file stored in disk, which is read into
+    /// memory at once and closed before returning. Unlike `from_file`, this doesn't keep the
+    /// file open, which on Windows would prevent it from being deleted or moved while the
+    /// `Player` exists, at the cost of holding the whole file in memory. It's best suited for
+    /// short sounds.
+    pub fn from_file_buffered<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_bytes(fs::read(path)?)
+    }
 
-        let file_len = file.seek(SeekFrom::End(0))?;
+    /// Create a new `Player` instance from the contents of a `.wav` file held in memory, such as
+    /// a sound embedded in the binary with `include_bytes!`.
+    pub fn from_bytes<B: Into<Vec<u8>>>(data: B) -> io::Result<Self> {
+        Self::from_reader(Cursor::new(data.into()))
+    }
 
-        const WF_OFFSET_DATA_SUBCHUNK: u64 = 36;
-        file.seek(SeekFrom::Start(WF_OFFSET_DATA_SUBCHUNK))?;
+    /// Create a new `Player` instance from a reader over the contents of a `.wav` file, which
+    /// the player takes ownership of.
+    ///
+    /// The file is read with a `WavReader`, so the chunks in the file may come in any order.
+    /// Big-endian `RIFX` files are also supported, and their samples are converted as they're
+    /// played.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use winaudio::wave::{Format, Player};
+    ///
+    /// let fmt = Format::pcm(1, 8000, 8);
+    /// let mut wav = Vec::new();
+    /// wav.extend_from_slice(b"RIFF\0\0\0\0WAVE");
+    /// wav.extend_from_slice(b"fmt \x10\0\0\0");
+    /// wav.extend_from_slice(&1u16.to_le_bytes()); // format tag
+    /// wav.extend_from_slice(&fmt.channels.to_le_bytes());
+    /// wav.extend_from_slice(&fmt.samples_per_sec.to_le_bytes());
+    /// wav.extend_from_slice(&fmt.avg_bytes_per_sec.to_le_bytes());
+    /// wav.extend_from_slice(&fmt.block_align.to_le_bytes());
+    /// wav.extend_from_slice(&fmt.bits_per_sample.to_le_bytes());
+    /// wav.extend_from_slice(b"bext\x03\0\0\0abc\0"); // odd length, followed by a pad byte
+    /// wav.extend_from_slice(b"data\x40\x1f\0\0");
+    /// wav.extend_from_slice(&[128; 8000]);
+    ///
+    /// let player = Player::from_bytes(wav).unwrap();
+    /// assert_eq!(player.format(), fmt);
+    /// assert_eq!(player.duration(), Duration::from_secs(1));
+    /// ```
+    ///
+    /// The samples of a `RIFX` file are played in the little-endian order devices expect:
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use winaudio::wave::{tone, Format, Player, WaveOut};
+    /// use winaudio::Error;
+    ///
+    /// # // Output that keeps every write instead of playing it, as in the example of `play_to`.
+    /// # #[derive(Default)]
+    /// # struct Recorder {
+    /// #     writes: Vec<Vec<u8>>,
+    /// # }
+    /// # impl WaveOut for Recorder {
+    /// #     fn write_samples(&mut self, data: &[u8]) -> Result<(), Error> {
+    /// #         self.writes.push(data.to_vec());
+    /// #         Ok(())
+    /// #     }
+    /// #     fn set_volume(&mut self, _left: f32, _right: f32) -> Result<(), Error> {
+    /// #         Ok(())
+    /// #     }
+    /// #     fn pause(&mut self) -> Result<(), Error> {
+    /// #         Ok(())
+    /// #     }
+    /// #     fn resume(&mut self) -> Result<(), Error> {
+    /// #         Ok(())
+    /// #     }
+    /// #     fn stop(&mut self) -> Result<(), Error> {
+    /// #         Ok(())
+    /// #     }
+    /// #     fn wait(&self) {}
+    /// # }
+    /// let fmt = Format::pcm(1, 8000, 16);
+    /// let samples = tone(440.0, Duration::from_millis(100), &fmt);
+    ///
+    /// let mut rifx = Vec::new();
+    /// rifx.extend_from_slice(b"RIFX");
+    /// rifx.extend_from_slice(&(36 + samples.len() as u32).to_be_bytes());
+    /// rifx.extend_from_slice(b"WAVEfmt \0\0\0\x10");
+    /// rifx.extend_from_slice(&1u16.to_be_bytes()); // format tag
+    /// rifx.extend_from_slice(&fmt.channels.to_be_bytes());
+    /// rifx.extend_from_slice(&fmt.samples_per_sec.to_be_bytes());
+    /// rifx.extend_from_slice(&fmt.avg_bytes_per_sec.to_be_bytes());
+    /// rifx.extend_from_slice(&fmt.block_align.to_be_bytes());
+    /// rifx.extend_from_slice(&fmt.bits_per_sample.to_be_bytes());
+    /// rifx.extend_from_slice(b"data");
+    /// rifx.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+    /// for sample in samples.chunks_exact(2) {
+    ///     rifx.extend_from_slice(&[sample[1], sample[0]]);
+    /// }
+    ///
+    /// let mut player = Player::from_bytes(rifx).unwrap();
+    /// assert_eq!(player.format(), fmt);
+    /// let mut out = Recorder::default();
+    /// player.play_to(&mut out).unwrap();
+    /// assert_eq!(out.writes.concat(), samples);
+    /// ```
+    pub fn from_reader<R: Read + Seek + Send + 'static>(mut reader: R) -> io::Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        match read_riff_header(&mut reader)? {
+            Some(_) => {}
+            #[cfg(feature = "decode")]
+            None => return Self::decode(reader),
+            #[cfg(not(feature = "decode"))]
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "missing riff wave header",
+                ))
+            }
+        }
 
-        let mut data_id = [0; 4];
-        file.read(&mut data_id)?;
-        if &data_id != b"data" {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "unexpected data subchunk id",
-            ));
+        let wav = WavReader::new(Box::new(reader) as Box<dyn ReadSeek>)?;
+        #[cfg(feature = "decode")]
+        {
+            if !matches!(wav.format().format_tag, Tag::Pcm | Tag::IeeeFloat) {
+                return Self::decode(wav.into_inner());
+            }
         }
-        let meta_data_length = file.read_u32().unwrap() as u64;
 
-        const WF_OFFSET_DATA: u64 = 44;
-        let data_length = file_len - WF_OFFSET_DATA;
+        Ok(Self {
+            fmt: wav.format(),
+            data_len: wav.data_len(),
+            sample_length: wav.sample_length(),
+            bext: wav.broadcast_info(),
+            big_endian: wav.is_big_endian(),
+            source: Source::Wav(wav),
+            out: None,
+            volume: (1.0, 1.0),
+            resample: false,
+            reopen: false,
+            speed: 1.0,
+            resampled_speed: 1.0,
+            peak: 0.0,
+        })
+    }
 
-        if meta_data_length > data_length {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "format data length was greater than actual file length",
-            ));
+    /// Create a new `Player` instance from a reader over the contents of a `.wav` file which
+    /// can't seek, such as the standard input. The chunks are read strictly in order, and the
+    /// reader is left at the start of the `data` chunk, which must come after the `fmt ` chunk.
+    ///
+    /// The data can only be played once, as the stream can't go back to its beginning. The
+    /// `duration` and `data_len` are only as accurate as the length stated in the `data` chunk,
+    /// as they can't be checked against the actual stream. Files written while streaming often
+    /// state a length of zero or `u32::MAX`, in which case the data is played until the end of
+    /// the stream, and `data_len` is `u64::MAX`. Files that need decoding are not supported.
+    ///
+    /// ```
+    /// use std::io::{self, Cursor, Read};
+    /// use winaudio::wave::{write_wav_header, Format, Player};
+    ///
+    /// // Pipes may return less data than requested on every read.
+    /// struct Pipe(Cursor<Vec<u8>>);
+    ///
+    /// impl Read for Pipe {
+    ///     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    ///         let len = buf.len().min(1);
+    ///         self.0.read(&mut buf[..len])
+    ///     }
+    /// }
+    ///
+    /// let fmt = Format::pcm(1, 8000, 8);
+    /// let mut wav = Cursor::new(Vec::new());
+    /// write_wav_header(&mut wav, &fmt, 8000).unwrap();
+    ///
+    /// let player = Player::from_unseekable(Pipe(Cursor::new(wav.into_inner()))).unwrap();
+    /// assert_eq!(player.format(), fmt);
+    /// assert_eq!(player.data_len(), 8000);
+    /// ```
+    pub fn from_unseekable<R: Read + Send + 'static>(mut reader: R) -> io::Result<Self> {
+        let big_endian = read_riff_header(&mut reader)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "missing riff wave header")
+        })?;
+
+        let mut fmt = None;
+        let mut sample_length = None;
+        let mut bext = None;
+        let data_len = loop {
+            let mut id = [0; 4];
+            reader.read_exact(&mut id)?;
+            let size = read_u32(&mut reader, big_endian)? as u64;
+
+            let read = match &id {
+                b"fmt " => {
+                    fmt = Some(read_format(&mut reader, size, big_endian)?.0);
+                    size
+                }
+                b"fact" => {
+                    sample_length = Some(read_u32(&mut reader, big_endian)? as u64);
+                    4
+                }
+                b"bext" if size >= BroadcastExtension::LEN => {
+                    bext = Some(BroadcastExtension::read_from(&mut reader, big_endian)?);
+                    BroadcastExtension::LEN
+                }
+                b"data" if fmt.is_some() => break size,
+                b"data" => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "missing fmt subchunk before data subchunk",
+                    ))
+                }
+                _ => 0,
+            };
+
+            // Chunks are word-aligned, and the padding byte is not included in their size.
+            let skip = (size + (size & 1)).saturating_sub(read);
+            io::copy(&mut (&mut reader).take(skip), &mut io::sink())?;
+        };
+
+        Ok(Self {
+            fmt: fmt.unwrap(),
+            source: Source::Stream(Some(Box::new(reader))),
+            data_len: match data_len {
+                0 | 0xffffffff => u64::MAX,
+                len => len,
+            },
+            sample_length,
+            bext,
+            big_endian,
+            out: None,
+            volume: (1.0, 1.0),
+            resample: false,
+            reopen: false,
+            speed: 1.0,
+            resampled_speed: 1.0,
+            peak: 0.0,
+        })
+    }
+
+    /// Create a new `Player` which decodes the file from its beginning.
+    #[cfg(feature = "decode")]
+    fn decode<R: Read + Seek + Send + 'static>(mut reader: R) -> io::Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let decoder = Decoder::new(reader)?;
+        let fmt = decoder.format();
+        let sample_length = decoder.frame_count();
+
+        Ok(Self {
+            fmt,
+            source: Source::Decoded(Box::new(decoder)),
+            data_len: sample_length.unwrap_or(0) * fmt.block_align as u64,
+            sample_length,
+            bext: None,
+            big_endian: false,
+            out: None,
+            volume: (1.0, 1.0),
+            resample: false,
+            reopen: false,
+            speed: 1.0,
+            resampled_speed: 1.0,
+            peak: 0.0,
+        })
+    }
+
+    /// Format of the waveform-audio data in the file, or of the decoded data if it had to be
+    /// decoded.
+    pub fn format(&self) -> Format {
+        self.fmt
+    }
+
+    /// Length of the waveform-audio data in the file, in bytes, as stated by its `data` chunk.
+    /// Files with less data than stated are rejected when the `Player` is created.
+    ///
+    /// When the file is decoded, this is the length of the decoded data if known, or zero.
+    ///
+    /// ```
+    /// use winaudio::wave::{write_wav_header, Format, Player};
+    /// use std::io::{Cursor, ErrorKind};
+    ///
+    /// let mut wav = Cursor::new(Vec::new());
+    /// write_wav_header(&mut wav, &Format::pcm(1, 8000, 8), 100).unwrap();
+    /// let mut wav = wav.into_inner();
+    ///
+    /// wav.resize(wav.len() + 100, 128);
+    /// assert_eq!(Player::from_bytes(wav.clone()).unwrap().data_len(), 100);
+    ///
+    /// wav.truncate(wav.len() - 1);
+    /// let err = Player::from_bytes(wav).err().unwrap();
+    /// assert_eq!(err.kind(), ErrorKind::InvalidData);
+    /// ```
+    pub fn data_len(&self) -> u64 {
+        self.data_len
+    }
+
+    /// Metadata from the `bext` chunk, present in Broadcast Wave Format files.
+    ///
+    /// ```
+    /// use winaudio::wave::{write_wav_header, Format, Player};
+    /// use std::io::Cursor;
+    ///
+    /// let mut wav = Cursor::new(Vec::new());
+    /// write_wav_header(&mut wav, &Format::pcm(1, 8000, 8), 0).unwrap();
+    /// let mut wav = wav.into_inner();
+    ///
+    /// let mut bext = vec![0; 602];
+    /// bext[..6].copy_from_slice(b"Jingle");
+    /// bext[320..338].copy_from_slice(b"2020-01-3112-00-00");
+    /// bext[338..342].copy_from_slice(&8000u32.to_le_bytes());
+    /// wav.extend_from_slice(b"bext");
+    /// wav.extend_from_slice(&(bext.len() as u32).to_le_bytes());
+    /// wav.extend_from_slice(&bext);
+    ///
+    /// let info = Player::from_bytes(wav).unwrap().broadcast_info().unwrap();
+    /// assert_eq!(info.description, "Jingle");
+    /// assert_eq!(info.origination_date, "2020-01-31");
+    /// assert_eq!(info.origination_time, "12-00-00");
+    /// assert_eq!(info.time_reference, 8000);
+    /// ```
+    pub fn broadcast_info(&self) -> Option<BroadcastExtension> {
+        self.bext.clone()
+    }
+
+    /// Total duration of the waveform-audio data in the file.
+    ///
+    /// For compressed formats, the number of samples in the `fact` chunk is used if present,
+    /// as the data length is not proportional to the duration. Otherwise, the duration is
+    /// estimated from the data length and the average data-transfer rate.
+    pub fn duration(&self) -> Duration {
+        reader::duration(&self.fmt, self.sample_length, self.data_len)
+    }
+
+    /// Set the volume used to play the file. A value of 1.0 represents full volume, and 0.0
+    /// silence.
+    ///
+    /// If the file was played before, the volume of the device it was played on is changed right
+    /// away. Otherwise, the volume is applied once the device is opened by `play`.
+    pub fn set_volume(&mut self, left: f32, right: f32) -> io::Result<()> {
+        match &mut self.out {
+            Some(out) => out.set_volume(left, right)?,
+            None if !(0.0..=1.0).contains(&left) || !(0.0..=1.0).contains(&right) => {
+                return Err(Error::InvalidParam.into())
+            }
+            None => {}
+        }
+        self.volume = (left, right);
+        Ok(())
+    }
+
+    /// Set how fast to play the file, where 1.0 is the normal speed and 1.5 plays it one and a
+    /// half times as fast. The pitch changes along with the speed. If the file is playing, the
+    /// change applies right away.
+    ///
+    /// The playback rate of the device is used when it supports `Functionality::PlaybackRate`.
+    /// Otherwise, the data is resampled instead, which only applies from the next `play`. Only
+    /// 16-bit PCM data can be resampled, so playing any other data at a different speed fails
+    /// with `Error::NotSupported` on such devices. The speed must be greater than zero.
+    pub fn set_speed(&mut self, speed: f32) -> io::Result<()> {
+        if !speed.is_finite() || speed <= 0.0 {
+            return Err(Error::InvalidParam.into());
+        }
+        let can_resample = self.can_resample();
+        if let Some(out) = &mut self.out {
+            self.resampled_speed = apply_speed(out, speed, can_resample)?;
         }
+        self.speed = speed;
+        Ok(())
+    }
 
-        Ok(Self { fmt, file })
+    /// Set whether `play` should resample the data to the nearest sample rate supported by the
+    /// device when it can't play the sample rate of the file (and `Out::open` fails with
+    /// `Error::BadFormat`). Only 16-bit PCM data can be resampled, and it's disabled by default.
+    ///
+    /// The resampling is done with `wave::resample`, which is a basic resampler.
+    pub fn set_resample(&mut self, enabled: bool) {
+        self.resample = enabled;
+    }
+
+    /// Set whether the playback started with `spawn` should open the `device::WAVE_MAPPER` again
+    /// when the device it was playing on stops working, such as when headphones are unplugged,
+    /// and continue from where it was on the device that's now the default. It's disabled by
+    /// default, so such errors end the playback.
+    ///
+    /// The loss of the device is only noticed once writing to it fails, and the data it had
+    /// queued is lost, so there is a gap in the playback of up to two buffers plus the time it
+    /// takes to open the new device. If the default device changes but the old one keeps
+    /// working, the playback stays on the old one. Only `.wav` files that can seek can continue
+    /// on a new device.
+    pub fn reopen_device_on_default_change(&mut self, enabled: bool) {
+        self.reopen = enabled;
+    }
+
+    /// Loudest sample in the last chunk of data written by `play`, from 0.0 (silence) to 1.0
+    /// (full scale), which is the chunk still being played right after it returns. This is zero
+    /// if the file wasn't played yet, or if its format is not PCM or IEEE float.
+    pub fn peak_level(&self) -> f32 {
+        self.peak
     }
 
     /// Play the file from beginning to end.
+    ///
+    /// This returns as soon as the last chunk of data has been written, and the device keeps
+    /// playing it in the background until it's done, or until the `Player` is dropped. Playing
+    /// again waits for the previous playback to finish first, and reuses the same device.
+    ///
+    /// If there are no output devices at all, such as in a server with audio disabled, this
+    /// fails with an `io::ErrorKind::NotFound` error.
+    ///
+    /// ```
+    /// use std::io::ErrorKind;
+    /// use winaudio::device;
+    /// use winaudio::wave::{write_wav_header, Format, Player};
+    ///
+    /// let mut wav = std::io::Cursor::new(Vec::new());
+    /// write_wav_header(&mut wav, &Format::pcm(1, 8000, 8), 0).unwrap();
+    /// let mut player = Player::from_bytes(wav.into_inner()).unwrap();
+    ///
+    /// if device::count() == 0 {
+    ///     let err = player.play().unwrap_err();
+    ///     assert_eq!(err.kind(), ErrorKind::NotFound);
+    ///     assert_eq!(err.to_string(), "no audio output devices available");
+    /// }
+    /// ```
     pub fn play(&mut self) -> io::Result<()> {
-        let mut device = Out::open(WAVE_MAPPER, &self.fmt).map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("failed to open output audio device: {:?}", e),
-            )
-        })?;
+        self.play_with_progress(|_, _| {})
+    }
 
-        let mut buf_idx = false;
+    /// Like `play`, but call `on_progress` after every chunk of data is written to the device,
+    /// with the duration of all the data written so far and the total duration, if known. The
+    /// data written is at most one chunk ahead of what can actually be heard.
+    ///
+    /// ```no_run
+    /// use winaudio::wave::Player;
+    ///
+    /// let mut player = Player::from_file("test.wav").unwrap();
+    /// player
+    ///     .play_with_progress(|played, total| match total {
+    ///         Some(total) => println!("{:.1}s / {:.1}s", played.as_secs_f64(), total.as_secs_f64()),
+    ///         None => println!("{:.1}s", played.as_secs_f64()),
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn play_with_progress<F: FnMut(Duration, Option<Duration>)>(
+        &mut self,
+        on_progress: F,
+    ) -> io::Result<()> {
+        let total = self.known_duration();
+        self.play_bytes(0, u64::MAX, total, on_progress)
+    }
+
+    /// Play only the data between `start` and `end`, like `play`. Both are rounded down to a
+    /// whole number of frames.
+    ///
+    /// Returns an error if `start` is not before `end`, or `end` is past the `duration`. Only
+    /// `.wav` files that can seek can start anywhere other than at their beginning.
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use winaudio::wave::Player;
+    ///
+    /// let mut player = Player::from_file("test.wav").unwrap();
+    /// player
+    ///     .play_range(Duration::from_secs(1), Duration::from_secs(3))
+    ///     .unwrap();
+    /// ```
+    pub fn play_range(&mut self, start: Duration, end: Duration) -> io::Result<()> {
+        if start >= end || self.known_duration().is_some_and(|total| end > total) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the range must be non-empty and within the duration",
+            ));
+        }
+        let start_offset = self.fmt.duration_to_bytes(start);
+        let end_offset = self.fmt.duration_to_bytes(end);
+        self.play_bytes(
+            start_offset,
+            end_offset - start_offset,
+            Some(end - start),
+            |_, _| {},
+        )
+    }
+
+    /// Play at most `len` bytes of the data from the given offset, reporting the progress of
+    /// the playback towards the `total` duration.
+    fn play_bytes<F: FnMut(Duration, Option<Duration>)>(
+        &mut self,
+        offset: u64,
+        len: u64,
+        total: Option<Duration>,
+        mut on_progress: F,
+    ) -> io::Result<()> {
+        let mut written = 0;
+        let mut peak;
+
+        let mut device = self.take_device()?;
+        // The format differs from the one of the file if the data has to be resampled.
+        let fmt = device.format();
+        let speed = self.resampled_speed;
+        let mut data = self.playback_data(&fmt, offset, len)?;
+
+        // Each chunk fills one of the buffers of the device, and is written as soon as that
+        // buffer is free, so the device keeps playing the other ones in the meantime.
+        let mut buffer = chunk_buffer(&device);
         loop {
-            let full = device.buffers()[buf_idx as usize].read(&mut self.file)?;
-            match buf_idx {
-                false => {
-                    device.write_first().map_err(|_| {
-                        io::Error::new(io::ErrorKind::Other, "failed to write first buffer")
-                    })?;
+            let full = fill_buffer(&mut buffer, &mut data, &fmt)?;
+            device.write_chunked(buffer.as_slice())?;
+            written += buffer.as_slice().len() as u64;
+            peak = peak_level(&fmt, buffer.as_slice());
+            on_progress(fmt.bytes_to_duration(written).mul_f32(speed), total);
+
+            if !full {
+                break;
+            }
+        }
+
+        // The data borrows the player, so it must be gone before the device can be kept.
+        drop(data);
+        self.out = Some(device);
+        self.peak = peak;
+        Ok(())
+    }
+
+    /// Play the file from beginning to end like `play`, but between every chunk of data written,
+    /// apply the commands received from a `PlaybackHandle`. This returns once the device is done
+    /// playing, or after a `Command::Stop`.
+    pub(crate) fn play_commands(&mut self, commands: &Receiver<Command>) -> io::Result<()> {
+        let mut device = self.take_device()?;
+        let mut fmt = device.format();
+        let src_fmt = self.fmt;
+        let mut volume = self.volume;
+        let reopen = self.reopen;
+        let mut speed = self.resampled_speed;
+        let mut data = self.playback_data(&fmt, 0, u64::MAX)?;
+
+        // Where the data currently being read started, and how much of it was written since,
+        // to know where to continue from if the device has to be reopened.
+        let mut start = Duration::from_secs(0);
+        let mut written = 0;
+
+        let mut buffer = chunk_buffer(&device);
+        let mut done = false;
+        loop {
+            // Block while paused, as nothing else can happen until the playback is resumed, and
+            // once all data is written, wait for the device to finish without missing commands.
+            let command = if device.is_paused() {
+                match commands.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => {
+                        // Nobody can resume the playback anymore.
+                        device.stop()?;
+                        break;
+                    }
+                }
+            } else if done {
+                if device.queued_buffers() == 0 {
+                    break;
+                }
+                match commands.recv_timeout(COMMAND_POLL_INTERVAL) {
+                    Ok(command) => Some(command),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        device.wait();
+                        break;
+                    }
+                }
+            } else {
+                commands.try_recv().ok()
+            };
+
+            match command {
+                Some(Command::SetVolume(left, right)) => {
+                    device.set_volume(left, right)?;
+                    volume = (left, right);
                 }
-                true => {
-                    device.write_second().map_err(|_| {
-                        io::Error::new(io::ErrorKind::Other, "failed to write second buffer")
-                    })?;
+                Some(Command::Pause) => device.pause()?,
+                Some(Command::Resume) => device.resume()?,
+                Some(Command::Seek(position)) => {
+                    // Stopping the device resumes it, but seeking while paused should not.
+                    let paused = device.is_paused();
+                    device.stop()?;
+                    if paused {
+                        device.pause()?;
+                    }
+                    drop(data);
+                    let offset = src_fmt.duration_to_bytes(position);
+                    data = self.playback_data(&fmt, offset, u64::MAX)?;
+                    start = position;
+                    written = 0;
+                    done = false;
+                }
+                Some(Command::Stop) => {
+                    device.stop()?;
+                    break;
+                }
+                None if done => {}
+                None => {
+                    let full = fill_buffer(&mut buffer, &mut data, &fmt)?;
+                    let len = buffer.as_slice().len() as u64;
+                    match device.write_chunked(buffer.as_slice()) {
+                        Ok(()) => {}
+                        Err(e) if reopen && is_device_lost(e) => {
+                            // Continue from the buffer that failed on a new device. Whatever the
+                            // old device had queued but didn't play yet is lost.
+                            drop(data);
+                            let position = start + fmt.bytes_to_duration(written).mul_f32(speed);
+                            device = self.open_device()?;
+                            device.set_volume(volume.0, volume.1)?;
+                            self.init_speed(&mut device)?;
+                            speed = self.resampled_speed;
+                            fmt = device.format();
+                            buffer = chunk_buffer(&device);
+                            let offset = src_fmt.duration_to_bytes(position);
+                            data = self.playback_data(&fmt, offset, u64::MAX)?;
+                            start = position;
+                            written = 0;
+                            continue;
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                    written += len;
+                    done = !full;
                 }
             }
-            buf_idx = !buf_idx;
+        }
+
+        // The data borrows the player, so it must be gone before the device can be kept.
+        drop(data);
+        self.out = Some(device);
+        self.volume = volume;
+        Ok(())
+    }
+
+    /// Reuse the device from the previous playback once it's done, or open a new one with the
+    /// volume set for the player.
+    fn take_device(&mut self) -> io::Result<Out> {
+        let mut device = match self.out.take() {
+            Some(device) => {
+                device.wait();
+                device
+            }
+            None => {
+                let mut device = self.open_device()?;
+                device.set_volume(self.volume.0, self.volume.1)?;
+                device
+            }
+        };
+        self.init_speed(&mut device)?;
+        Ok(device)
+    }
+
+    /// Play at the speed set for the player on a device that's about to be used. Devices play at
+    /// the normal speed unless told otherwise, so the device is left alone at the normal speed,
+    /// as changing its playback rate may fail even then.
+    fn init_speed(&mut self, device: &mut Out) -> io::Result<()> {
+        self.resampled_speed = if self.speed == 1.0 {
+            1.0
+        } else {
+            apply_speed(device, self.speed, self.can_resample())?
+        };
+        Ok(())
+    }
+
+    /// Reader over at most `len` bytes of the waveform-audio data from the given offset,
+    /// converted to the format the device was opened with.
+    fn playback_data(
+        &mut self,
+        fmt: &Format,
+        offset: u64,
+        len: u64,
+    ) -> io::Result<Box<dyn Read + '_>> {
+        // Data that's sped up by resampling is treated as if it had a higher sample rate.
+        let rate = self.fmt.samples_per_sec as f64 * self.resampled_speed as f64;
+        let rate = (rate.round() as u32).max(1);
+        let data = self.data_range(offset, len)?;
+        if fmt.samples_per_sec == rate {
+            return Ok(data);
+        }
+        Ok(Box::new(Resampler::new(
+            data,
+            rate,
+            fmt.samples_per_sec,
+            fmt.channels,
+        )))
+    }
+
+    /// Whether the data can be resampled, which is only done for 16-bit PCM.
+    fn can_resample(&self) -> bool {
+        self.fmt.format_tag == Tag::Pcm && self.fmt.bits_per_sample == 16
+    }
+
+    /// Open the `WAVE_MAPPER` to play the data, with a different sample rate if it has to be
+    /// resampled.
+    fn open_device(&self) -> io::Result<Out> {
+        // Without devices, the mapper fails with an error that doesn't say why.
+        if device::count() == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no audio output devices available",
+            ));
+        }
+        // `Out::open` would only fail with `Error::BadFormat`, so report the reason instead.
+        self.fmt
+            .validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        if !self.resample || !self.can_resample() {
+            return Ok(Out::open(WAVE_MAPPER, &self.fmt)?);
+        }
+
+        match Out::open_best_match(WAVE_MAPPER, &self.fmt)? {
+            (device, fmt) if fmt.bits_per_sample == 16 => Ok(device),
+            _ => Err(Error::BadFormat.into()),
+        }
+    }
+
+    /// Total duration if it's known, which may not be the case for data that's decoded or that
+    /// comes from a stream.
+    fn known_duration(&self) -> Option<Duration> {
+        match self.source {
+            Source::Stream(_) if self.data_len == u64::MAX => None,
+            #[cfg(feature = "decode")]
+            Source::Decoded(_) if self.sample_length.is_none() => None,
+            _ => Some(self.duration()),
+        }
+    }
+
+    /// Play the file from beginning to end on a new thread, which owns the player and the device
+    /// until it's done. The returned handle can be used to control the playback from any other
+    /// thread, and to get the player back once the playback ends.
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use winaudio::wave::Player;
+    ///
+    /// let player = Player::from_file("test.wav").unwrap();
+    /// let handle = player.spawn();
+    /// handle.seek(Duration::from_secs(10));
+    /// handle.set_volume(0.5, 0.5);
+    /// let player = handle.join().unwrap();
+    /// ```
+    pub fn spawn(self) -> PlaybackHandle {
+        PlaybackHandle::spawn(self)
+    }
+
+    /// Play the file from beginning to end on the given output, which must have been opened
+    /// with the same `format`. Unlike `play`, this waits for the output to finish playing.
+    ///
+    /// This is mostly useful to test code using a `Player` with something other than `Out`. The
+    /// data is read into a buffer as large as the ones of `Out`, in the same way `play` does, so
+    /// every write is as large as one of the buffers `play` would send to the device.
+    ///
+    /// Only whole frames are ever written, no matter how large the samples are, and if the data
+    /// ends in the middle of a frame, the frame is completed with silence:
+    ///
+    /// ```
+    /// use std::io::{Cursor, Write};
+    /// use std::time::Duration;
+    /// use winaudio::wave::{tone, write_wav_header, Format, Player, WaveOut};
+    /// use winaudio::Error;
+    ///
+    /// // Output that keeps every write instead of playing it.
+    /// #[derive(Default)]
+    /// struct Recorder {
+    ///     writes: Vec<Vec<u8>>,
+    /// }
+    ///
+    /// impl WaveOut for Recorder {
+    ///     fn write_samples(&mut self, data: &[u8]) -> Result<(), Error> {
+    ///         self.writes.push(data.to_vec());
+    ///         Ok(())
+    ///     }
+    ///     fn set_volume(&mut self, _left: f32, _right: f32) -> Result<(), Error> {
+    ///         Ok(())
+    ///     }
+    ///     fn pause(&mut self) -> Result<(), Error> {
+    ///         Ok(())
+    ///     }
+    ///     fn resume(&mut self) -> Result<(), Error> {
+    ///         Ok(())
+    ///     }
+    ///     fn stop(&mut self) -> Result<(), Error> {
+    ///         Ok(())
+    ///     }
+    ///     fn wait(&self) {}
+    /// }
+    ///
+    /// let fmt = Format::ieee_float(2, 44100);
+    /// let samples = tone(440.0, Duration::from_secs(1), &fmt);
+    /// let mut wav = Cursor::new(Vec::new());
+    /// write_wav_header(&mut wav, &fmt, samples.len() as u32).unwrap();
+    /// wav.write_all(&samples).unwrap();
+    ///
+    /// let mut player = Player::from_bytes(wav.into_inner()).unwrap();
+    /// let mut out = Recorder::default();
+    /// player.play_to(&mut out).unwrap();
+    ///
+    /// let frame_len = fmt.block_align as usize;
+    /// assert!(out.writes.iter().all(|write| write.len() % frame_len == 0));
+    /// assert_eq!(out.writes.concat(), samples);
+    ///
+    /// // Five 16-bit samples, so the third frame is missing its right channel.
+    /// let fmt = Format::pcm(2, 8000, 16);
+    /// let mut wav = Cursor::new(Vec::new());
+    /// write_wav_header(&mut wav, &fmt, 10).unwrap();
+    /// wav.write_all(&[0x11; 10]).unwrap();
+    ///
+    /// let mut player = Player::from_bytes(wav.into_inner()).unwrap();
+    /// let mut out = Recorder::default();
+    /// player.play_to(&mut out).unwrap();
+    ///
+    /// let written = out.writes.concat();
+    /// assert_eq!(written.len(), 12);
+    /// assert_eq!(&written[..10], &[0x11; 10]);
+    /// assert_eq!(&written[10..], &[0, 0]);
+    /// ```
+    pub fn play_to<O: WaveOut>(&mut self, out: &mut O) -> io::Result<()> {
+        let fmt = self.fmt;
+        let size = Out::BUFFER_SIZE.next_multiple_of(fmt.block_align.max(1) as usize);
+        let mut buffer = Buffer::new(vec![0; size].into_boxed_slice());
+        let mut data = self.data()?;
+        loop {
+            let full = fill_buffer(&mut buffer, &mut data, &fmt)?;
+            if !buffer.as_slice().is_empty() {
+                out.write_samples(buffer.as_slice())?;
+            }
             if !full {
                 break;
             }
         }
-
+        out.wait();
         Ok(())
     }
+
+    /// Reader over the waveform-audio data from its beginning.
+    fn data(&mut self) -> io::Result<Box<dyn Read + '_>> {
+        self.data_range(0, u64::MAX)
+    }
+
+    /// Reader over at most `len` bytes of the waveform-audio data from the given offset, which
+    /// must be zero unless the data comes from a `.wav` file that can seek.
+    fn data_range(&mut self, offset: u64, len: u64) -> io::Result<Box<dyn Read + '_>> {
+        let offset = offset.min(self.data_len);
+        let len = len.min(self.data_len - offset);
+        let data: Box<dyn Read + '_> = match &mut self.source {
+            Source::Wav(wav) => Box::new(wav.data_range(offset, len)?),
+            _ if offset != 0 => {
+                return Err(io::Error::other(
+                    "only the data of .wav files can be seeked",
+                ))
+            }
+            Source::Stream(reader) => {
+                let reader = reader
+                    .take()
+                    .ok_or_else(|| io::Error::other("the stream was already played"))?;
+                Box::new(reader.take(len))
+            }
+            #[cfg(feature = "decode")]
+            Source::Decoded(decoder) => {
+                decoder.rewind()?;
+                Box::new(decoder.take(len))
+            }
+        };
+
+        let sample_size = (self.fmt.bits_per_sample / 8) as usize;
+        if self.big_endian && sample_size > 1 {
+            Ok(Box::new(SwapBytes {
+                inner: data,
+                sample_size,
+            }))
+        } else {
+            Ok(data)
+        }
+    }
+}
+
+/// Play at the given speed by changing the playback rate of the device if it can, and return
+/// how much of the speed is left to be done by resampling the data (1.0 if none).
+fn apply_speed(device: &mut Out, speed: f32, can_resample: bool) -> io::Result<f32> {
+    match device.set_playback_rate(speed) {
+        Ok(()) => Ok(1.0),
+        Err(Error::NotSupported) if speed == 1.0 || can_resample => Ok(speed),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether the error means that the device is gone, so another one should be used.
+fn is_device_lost(error: Error) -> bool {
+    matches!(
+        error,
+        Error::Error
+            | Error::BadDeviceId
+            | Error::NotEnabled
+            | Error::InvalidHandle
+            | Error::NoDriver
+    )
+}
+
+/// Buffer to read the data into before writing it to the device, as large as the ones of the
+/// device so that every chunk fills one of them.
+fn chunk_buffer(device: &Out) -> Buffer {
+    Buffer::new(vec![0; device.buffer_size()].into_boxed_slice())
+}
+
+/// Read the next chunk of data into the buffer. Returns `false` if the end of the data was
+/// reached.
+fn fill_buffer<R: Read>(buffer: &mut Buffer, data: &mut R, fmt: &Format) -> io::Result<bool> {
+    let outcome = buffer.read(data, fmt.block_align)?;
+    if !outcome.frame_aligned {
+        // A truncated file may end in the middle of a block, which would click if played.
+        // The rest of the buffer is zeroed, and its size is a whole number of blocks.
+        let align = fmt.block_align.max(1) as usize;
+        buffer.set_valid_len(outcome.bytes.div_ceil(align) * align)?;
+    }
+    Ok(!outcome.eof)
+}
+
+/// Reader that converts big-endian samples into the little-endian ones the device expects.
+struct SwapBytes<R> {
+    inner: R,
+    sample_size: usize,
+}
+
+impl<R: Read> Read for SwapBytes<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Only whole samples can be swapped, so keep reading until the last one is complete.
+        let len = buf.len() - buf.len() % self.sample_size;
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "buffer too small to hold a sample",
+            ));
+        }
+
+        let mut read = self.inner.read(&mut buf[..len])?;
+        if read % self.sample_size != 0 {
+            let end = read + self.sample_size - read % self.sample_size;
+            read += read_chunk(&mut self.inner, &mut buf[read..end])?;
+        }
+        buf[..read]
+            .chunks_exact_mut(self.sample_size)
+            .for_each(|sample| sample.reverse());
+        Ok(read)
+    }
+}
+
+/// Loudest sample in the data, from 0.0 to 1.0, or zero if the format is not PCM or IEEE float.
+fn peak_level(fmt: &Format, data: &[u8]) -> f32 {
+    let sample_size = (fmt.bits_per_sample / 8) as usize;
+    if sample_size == 0 {
+        return 0.0;
+    }
+
+    let samples = data.chunks_exact(sample_size);
+    let peak = match (fmt.format_tag, sample_size) {
+        (Tag::IeeeFloat, 4) => samples
+            .map(|s| f32::from_le_bytes(s.try_into().unwrap()).abs())
+            .fold(0.0, f32::max),
+        (Tag::IeeeFloat, 8) => samples
+            .map(|s| f64::from_le_bytes(s.try_into().unwrap()).abs() as f32)
+            .fold(0.0, f32::max),
+        // 8-bit samples are unsigned, with silence at the midpoint.
+        (Tag::Pcm, 1) => samples
+            .map(|s| (s[0] as f32 - 128.0).abs() / 128.0)
+            .fold(0.0, f32::max),
+        (Tag::Pcm, 2..=4) => samples
+            .map(|s| {
+                // Place the sample in the most significant bytes so that it's sign-extended.
+                let mut raw = [0; 4];
+                raw[4 - sample_size..].copy_from_slice(s);
+                (i32::from_le_bytes(raw) as f32).abs() / 2147483648.0
+            })
+            .fold(0.0, f32::max),
+        _ => 0.0,
+    };
+    peak.min(1.0)
+}
+
+/// Play `a` from beginning to end, and during its last `overlap`, start playing `b` while fading
+/// out `a` and fading in `b`, so that there's no gap between them.
+///
+/// Each player is played on its own output device opened with `device::WAVE_MAPPER`, and the
+/// volume of each device is ramped independently. True simultaneous playback needs either two
+/// devices or a software mixer, so this will fail if the device can't be opened twice.
+///
+/// The devices are written to in turn, one chunk at a time. Each write only waits for one of the
+/// buffers of that device to be free, while the rest keep playing, so neither device runs out of
+/// data while the other one is being written to.
+pub fn crossfade(mut a: Player, mut b: Player, overlap: Duration) -> io::Result<()> {
+    // How often the volume is updated during the overlap.
+    const STEP: Duration = Duration::from_millis(100);
+
+    let mut out_a = Out::open(WAVE_MAPPER, &a.fmt)?;
+    let mut out_b = Out::open(WAVE_MAPPER, &b.fmt)?;
+    out_b.set_volume(0.0, 0.0)?;
+
+    let overlap_len = a.fmt.duration_to_bytes(overlap).min(a.data_len).max(1);
+    // The length of decoded data may be unknown, in which case the fade starts right away.
+    let fade_start = a.data_len.saturating_sub(overlap_len);
+    let mut chunk_a = vec![0; step_len(&a.fmt, STEP)];
+    let mut chunk_b = vec![0; step_len(&b.fmt, STEP)];
+
+    let mut data_a = a.data()?;
+    let mut data_b = b.data()?;
+    let mut played = 0;
+    loop {
+        let read = read_chunk(&mut data_a, &mut chunk_a)?;
+        if read == 0 {
+            break;
+        }
+        out_a.write_chunked(&chunk_a[..read])?;
+        played += read as u64;
+
+        if played > fade_start {
+            let t = ((played - fade_start) as f32 / overlap_len as f32).min(1.0);
+            out_a.set_volume(1.0 - t, 1.0 - t)?;
+            out_b.set_volume(t, t)?;
+
+            let read = read_chunk(&mut data_b, &mut chunk_b)?;
+            if read != 0 {
+                out_b.write_chunked(&chunk_b[..read])?;
+            }
+        }
+    }
+
+    // Keep feeding `b` while `a` plays what it has left, instead of waiting for it to finish.
+    out_b.set_volume(1.0, 1.0)?;
+    loop {
+        let read = read_chunk(&mut data_b, &mut chunk_b)?;
+        if read == 0 {
+            break;
+        }
+        out_b.write_chunked(&chunk_b[..read])?;
+    }
+    out_a.wait();
+    out_b.wait();
+
+    Ok(())
+}
+
+/// Number of bytes needed to play each step, which must be at least one frame.
+fn step_len(fmt: &Format, step: Duration) -> usize {
+    fmt.duration_to_bytes(step)
+        .max(fmt.bytes_per_frame().max(1) as u64) as usize
 }
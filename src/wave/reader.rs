@@ -0,0 +1,258 @@
+use crate::util::BinaryRead as _;
+use crate::wave::format::ChannelMask;
+use crate::wave::{BroadcastExtension, Format};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::time::Duration;
+
+/// Offset of the first chunk inside the `RIFF` chunk, after its header and the `WAVE` id.
+const WF_OFFSET_FIRST_CHUNK: u64 = 12;
+/// Length of the header of each chunk, made up of its id and its size.
+const CHUNK_HEADER_LEN: u64 = 8;
+
+/// Reader of `.wav` files, which finds the format, metadata and data in the file without
+/// playing it.
+///
+/// The chunks in the file may come in any order, and any chunk other than `fmt `, `fact`,
+/// `bext` and `data` is skipped, including the pad byte that follows chunks of odd length.
+/// Big-endian `RIFX` files are also supported, although their data is read as-is.
+///
+/// ```
+/// use std::io::{Cursor, Read};
+/// use std::time::Duration;
+/// use winaudio::wave::{write_wav_header, Format, WavReader};
+///
+/// let fmt = Format::pcm(1, 8000, 8);
+/// let mut wav = Cursor::new(Vec::new());
+/// write_wav_header(&mut wav, &fmt, 8000).unwrap();
+/// let mut wav = wav.into_inner();
+/// wav.resize(wav.len() + 8000, 128);
+///
+/// let mut reader = WavReader::new(Cursor::new(wav)).unwrap();
+/// assert_eq!(reader.format(), fmt);
+/// assert_eq!(reader.duration(), Duration::from_secs(1));
+///
+/// let mut data = Vec::new();
+/// reader.data_reader().unwrap().read_to_end(&mut data).unwrap();
+/// assert_eq!(data, vec![128; 8000]);
+/// ```
+pub struct WavReader<R> {
+    reader: R,
+    fmt: Format,
+    channel_mask: Option<ChannelMask>,
+    data_offset: u64,
+    data_len: u64,
+    sample_length: Option<u64>,
+    bext: Option<BroadcastExtension>,
+    big_endian: bool,
+}
+
+impl<R: Read + Seek> WavReader<R> {
+    /// Walk over all the chunks of the `.wav` file to find its format and data. The file must
+    /// have both a `fmt ` and a `data` chunk, and it can't have less data than stated by the
+    /// `data` chunk.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let big_endian = read_riff_header(&mut reader)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "missing riff wave header")
+        })?;
+
+        // Walk over all the chunks, as the ones we need may be in any order.
+        let mut fmt = None;
+        let mut channel_mask = None;
+        let mut sample_length = None;
+        let mut bext = None;
+        let mut data = None;
+        let mut offset = WF_OFFSET_FIRST_CHUNK;
+        while offset + CHUNK_HEADER_LEN <= file_len {
+            reader.seek(SeekFrom::Start(offset))?;
+            let mut id = [0; 4];
+            reader.read_exact(&mut id)?;
+            let size = read_u32(&mut reader, big_endian)? as u64;
+
+            match &id {
+                b"fmt " => {
+                    let (format, mask) = read_format(&mut reader, size, big_endian)?;
+                    fmt = Some(format);
+                    channel_mask = mask;
+                }
+                b"fact" => sample_length = Some(read_u32(&mut reader, big_endian)? as u64),
+                b"bext" if size >= BroadcastExtension::LEN => {
+                    bext = Some(BroadcastExtension::read_from(&mut reader, big_endian)?)
+                }
+                b"data" => data = Some((offset + CHUNK_HEADER_LEN, size)),
+                _ => {}
+            }
+
+            // Chunks are word-aligned, and the padding byte is not included in their size.
+            offset += CHUNK_HEADER_LEN + size + (size & 1);
+        }
+
+        let fmt =
+            fmt.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing fmt subchunk"))?;
+        let (data_offset, data_len) = data
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing data subchunk"))?;
+
+        // Files that were truncated, or written while streaming, may claim to have more data.
+        if data_offset + data_len > file_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "data subchunk length ({} bytes) was greater than actual file length ({} bytes)",
+                    data_len,
+                    file_len - data_offset
+                ),
+            ));
+        }
+        reader.seek(SeekFrom::Start(data_offset))?;
+
+        Ok(Self {
+            reader,
+            fmt,
+            channel_mask,
+            data_offset,
+            data_len,
+            sample_length,
+            bext,
+            big_endian,
+        })
+    }
+
+    /// Reader over the waveform-audio data in the `data` chunk, from its beginning, which ends
+    /// where the chunk does.
+    pub fn data_reader(&mut self) -> io::Result<io::Take<&mut R>> {
+        self.data_range(0, self.data_len)
+    }
+
+    /// Reader over at most `len` bytes of the waveform-audio data from the given offset, which
+    /// must not be past the end of the data.
+    pub(crate) fn data_range(&mut self, offset: u64, len: u64) -> io::Result<io::Take<&mut R>> {
+        self.reader
+            .seek(SeekFrom::Start(self.data_offset + offset))?;
+        Ok((&mut self.reader).take(len))
+    }
+}
+
+impl<R> WavReader<R> {
+    /// Format of the waveform-audio data in the file.
+    pub fn format(&self) -> Format {
+        self.fmt
+    }
+
+    /// Speaker position of each channel as stated by the file, which is only present in
+    /// `WAVE_FORMAT_EXTENSIBLE` formats. Otherwise, `Format::channel_mask` gives the default
+    /// layout used for the number of channels.
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use winaudio::wave::format::ChannelMask;
+    /// use winaudio::wave::WavReader;
+    ///
+    /// let mut fmt = Vec::new();
+    /// fmt.extend_from_slice(&0xfffeu16.to_le_bytes()); // format tag
+    /// fmt.extend_from_slice(&6u16.to_le_bytes()); // channels
+    /// fmt.extend_from_slice(&48000u32.to_le_bytes()); // samples per second
+    /// fmt.extend_from_slice(&(48000u32 * 12).to_le_bytes()); // average bytes per second
+    /// fmt.extend_from_slice(&12u16.to_le_bytes()); // block align
+    /// fmt.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    /// fmt.extend_from_slice(&22u16.to_le_bytes()); // extension size
+    /// fmt.extend_from_slice(&16u16.to_le_bytes()); // valid bits per sample
+    /// fmt.extend_from_slice(&0x60fu32.to_le_bytes()); // channel mask
+    /// fmt.extend_from_slice(&[0; 16]); // subformat
+    ///
+    /// let mut wav = Vec::new();
+    /// wav.extend_from_slice(b"RIFF\0\0\0\0WAVEfmt ");
+    /// wav.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+    /// wav.extend_from_slice(&fmt);
+    /// wav.extend_from_slice(b"data\0\0\0\0");
+    ///
+    /// // 5.1 with side speakers instead of back ones.
+    /// let reader = WavReader::new(Cursor::new(wav)).unwrap();
+    /// let mask = reader.channel_mask().unwrap();
+    /// assert!(mask.contains(ChannelMask::SIDE_LEFT | ChannelMask::SIDE_RIGHT));
+    /// assert_ne!(Some(mask), reader.format().channel_mask());
+    /// ```
+    pub fn channel_mask(&self) -> Option<ChannelMask> {
+        self.channel_mask
+    }
+
+    /// Length of the waveform-audio data in the file, in bytes, as stated by its `data` chunk.
+    pub fn data_len(&self) -> u64 {
+        self.data_len
+    }
+
+    /// Number of samples as stored in the `fact` chunk, usually present for compressed formats.
+    pub fn sample_length(&self) -> Option<u64> {
+        self.sample_length
+    }
+
+    /// Metadata from the `bext` chunk, present in Broadcast Wave Format files.
+    pub fn broadcast_info(&self) -> Option<BroadcastExtension> {
+        self.bext.clone()
+    }
+
+    /// Whether the file is a `RIFX` file, with big-endian samples.
+    pub fn is_big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    /// Total duration of the waveform-audio data in the file.
+    ///
+    /// For compressed formats, the number of samples in the `fact` chunk is used if present,
+    /// as the data length is not proportional to the duration. Otherwise, the duration is
+    /// estimated from the data length and the average data-transfer rate.
+    pub fn duration(&self) -> Duration {
+        duration(&self.fmt, self.sample_length, self.data_len)
+    }
+
+    /// Get back the reader over the file, at whichever position it was left.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Duration of the data, from the number of samples if known, or from its length otherwise.
+pub(crate) fn duration(fmt: &Format, sample_length: Option<u64>, data_len: u64) -> Duration {
+    match sample_length {
+        Some(samples) if fmt.samples_per_sec != 0 => {
+            Duration::from_secs_f64(samples as f64 / fmt.samples_per_sec as f64)
+        }
+        _ => fmt.bytes_to_duration(data_len),
+    }
+}
+
+/// Read the header of a `RIFF` file, which must be of `WAVE` type. Returns whether it's a `RIFX`
+/// file, with big-endian data, or `None` if it's not a `.wav` file at all.
+pub(crate) fn read_riff_header<R: Read>(reader: &mut R) -> io::Result<Option<bool>> {
+    let mut riff_id = [0; 4];
+    let mut wave_id = [0; 4];
+    reader.read_exact(&mut riff_id)?;
+    reader.read_u32()?;
+    reader.read_exact(&mut wave_id)?;
+    Ok(match (&riff_id, &wave_id) {
+        (b"RIFF", b"WAVE") => Some(false),
+        (b"RIFX", b"WAVE") => Some(true),
+        _ => None,
+    })
+}
+
+pub(crate) fn read_u32<R: Read>(reader: &mut R, big_endian: bool) -> io::Result<u32> {
+    let value = reader.read_u32()?;
+    Ok(if big_endian {
+        value.swap_bytes()
+    } else {
+        value
+    })
+}
+
+/// Read the format from a `fmt ` chunk of the given size, along with its channel mask if any.
+pub(crate) fn read_format<R: Read>(
+    reader: &mut R,
+    size: u64,
+    big_endian: bool,
+) -> io::Result<(Format, Option<ChannelMask>)> {
+    let mut chunk = Vec::new();
+    reader.take(size).read_to_end(&mut chunk)?;
+    Format::from_chunk(&chunk, big_endian)
+}
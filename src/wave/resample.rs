@@ -0,0 +1,146 @@
+//! Basic sample-rate conversion of 16-bit samples.
+use crate::util::read_chunk;
+use std::io::{self, Read};
+
+/// Convert interleaved 16-bit samples from one sample rate to another by linear interpolation
+/// between neighbouring frames.
+///
+/// This is a basic resampler, good enough for voice or notification sounds, but not a polyphase
+/// one, so it does not filter out aliasing and high frequencies will be distorted. Any trailing
+/// samples that don't make up a whole frame are ignored.
+///
+/// ```
+/// use winaudio::wave::resample;
+///
+/// let upsampled = resample(&[0, 100, 200], 1, 2, 1);
+/// assert_eq!(upsampled, vec![0, 50, 100, 150, 200, 200]);
+/// ```
+pub fn resample(input: &[i16], from_rate: u32, to_rate: u32, channels: u16) -> Vec<i16> {
+    let channels = channels as usize;
+    if channels == 0 || from_rate == 0 || to_rate == 0 {
+        return Vec::new();
+    }
+
+    let input = &input[..input.len() - input.len() % channels];
+    let frames = (input.len() / channels) as u64;
+    let mut output = Vec::with_capacity((frames * to_rate as u64 / from_rate as u64) as usize);
+    let mut pos = 0.0;
+    interpolate(
+        input,
+        channels,
+        from_rate as f64 / to_rate as f64,
+        &mut pos,
+        true,
+        &mut output,
+    );
+    output
+}
+
+/// Interpolate frames from `input` starting at `pos`, advancing `step` frames of the input for
+/// each frame of the output, and update `pos` to the next frame that would be produced.
+///
+/// Unless `end` is true, frames are only produced while the next input frame is available,
+/// so that the rest can be produced once more input arrives. Otherwise, the last frame is
+/// repeated as needed.
+fn interpolate(
+    input: &[i16],
+    channels: usize,
+    step: f64,
+    pos: &mut f64,
+    end: bool,
+    output: &mut Vec<i16>,
+) {
+    let frames = input.len() / channels;
+    while (*pos as usize) < frames && (end || (*pos as usize) + 1 < frames) {
+        let index = *pos as usize;
+        let next = (index + 1).min(frames - 1);
+        let frac = *pos - index as f64;
+        for c in 0..channels {
+            let a = input[index * channels + c] as f64;
+            let b = input[next * channels + c] as f64;
+            output.push((a + (b - a) * frac).round() as i16);
+        }
+        *pos += step;
+    }
+}
+
+/// Reader that resamples the 16-bit little-endian samples of another reader as they're read.
+pub(crate) struct Resampler<R> {
+    inner: R,
+    channels: usize,
+    step: f64,
+    // Input frames not fully consumed yet, and position in them of the next output frame.
+    input: Vec<i16>,
+    pos: f64,
+    // Output bytes not read yet, and whether the inner reader reached its end.
+    output: Vec<u8>,
+    offset: usize,
+    done: bool,
+}
+
+impl<R: Read> Resampler<R> {
+    /// Number of input frames read from the inner reader at a time.
+    const CHUNK_FRAMES: usize = 4096;
+
+    pub(crate) fn new(inner: R, from_rate: u32, to_rate: u32, channels: u16) -> Self {
+        Self {
+            inner,
+            channels: channels.max(1) as usize,
+            step: from_rate as f64 / to_rate.max(1) as f64,
+            input: Vec::new(),
+            pos: 0.0,
+            output: Vec::new(),
+            offset: 0,
+            done: false,
+        }
+    }
+
+    /// Read the next chunk of input and resample as much of it as possible into `output`.
+    fn refill(&mut self) -> io::Result<()> {
+        let mut raw = vec![0; Self::CHUNK_FRAMES * self.channels * 2];
+        let read = read_chunk(&mut self.inner, &mut raw)?;
+        let read = read - read % (self.channels * 2);
+        self.done = read == 0;
+        self.input.extend(
+            raw[..read]
+                .chunks_exact(2)
+                .map(|s| i16::from_le_bytes([s[0], s[1]])),
+        );
+
+        let mut samples = Vec::new();
+        interpolate(
+            &self.input,
+            self.channels,
+            self.step,
+            &mut self.pos,
+            self.done,
+            &mut samples,
+        );
+        self.output.clear();
+        self.output
+            .extend(samples.iter().flat_map(|s| s.to_le_bytes().to_vec()));
+        self.offset = 0;
+
+        // Only the frames from the current position onwards are needed from now on.
+        let consumed = (self.pos as usize).min(self.input.len() / self.channels);
+        self.input.drain(..consumed * self.channels);
+        self.pos -= consumed as f64;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Resampler<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.offset == self.output.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.refill()?;
+        }
+
+        let len = buf.len().min(self.output.len() - self.offset);
+        buf[..len].copy_from_slice(&self.output[self.offset..self.offset + len]);
+        self.offset += len;
+        Ok(len)
+    }
+}
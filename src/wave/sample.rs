@@ -0,0 +1,69 @@
+use crate::wave::format::Tag;
+
+mod private {
+    pub trait Sealed {}
+
+    impl Sealed for u8 {}
+    impl Sealed for i16 {}
+    impl Sealed for i32 {}
+    impl Sealed for f32 {}
+}
+
+/// Type of the samples in waveform-audio data of a given format, so that the data can be
+/// written with `Out::write_typed` without converting it to bytes first.
+///
+/// It's implemented for `u8` (8-bit PCM, which is unsigned), `i16` and `i32` (16-bit and 32-bit
+/// PCM), and `f32` (32-bit IEEE float), and can't be implemented outside of this crate.
+///
+/// ```
+/// use winaudio::wave::format::Tag;
+/// use winaudio::wave::Sample;
+///
+/// assert_eq!(<i16 as Sample>::FORMAT_TAG, Tag::Pcm);
+/// assert_eq!(<f32 as Sample>::BITS_PER_SAMPLE, 32);
+/// ```
+pub trait Sample: Copy + private::Sealed {
+    /// Format tag of data made of these samples.
+    const FORMAT_TAG: Tag;
+    /// Size of each sample, in bits.
+    const BITS_PER_SAMPLE: u16;
+
+    /// Append the little-endian bytes of the sample, as the device expects them.
+    fn extend_le_bytes(self, bytes: &mut Vec<u8>);
+}
+
+impl Sample for u8 {
+    const FORMAT_TAG: Tag = Tag::Pcm;
+    const BITS_PER_SAMPLE: u16 = 8;
+
+    fn extend_le_bytes(self, bytes: &mut Vec<u8>) {
+        bytes.push(self);
+    }
+}
+
+impl Sample for i16 {
+    const FORMAT_TAG: Tag = Tag::Pcm;
+    const BITS_PER_SAMPLE: u16 = 16;
+
+    fn extend_le_bytes(self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Sample for i32 {
+    const FORMAT_TAG: Tag = Tag::Pcm;
+    const BITS_PER_SAMPLE: u16 = 32;
+
+    fn extend_le_bytes(self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Sample for f32 {
+    const FORMAT_TAG: Tag = Tag::IeeeFloat;
+    const BITS_PER_SAMPLE: u16 = 32;
+
+    fn extend_le_bytes(self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.to_le_bytes());
+    }
+}
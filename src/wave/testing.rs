@@ -0,0 +1,116 @@
+//! Utilities to test code built on top of this crate without a real output device.
+use crate::wave::{Format, WaveOut};
+use crate::Error;
+
+/// Output device that doesn't play anything, and instead records everything written to it.
+///
+/// ```
+/// use winaudio::wave::testing::MockOut;
+/// use winaudio::wave::{write_wav_header, Format, Player};
+/// use std::io::{Cursor, Write};
+///
+/// let fmt = Format::pcm(1, 8000, 8);
+/// let samples = (0..8000).map(|i| (i % 256) as u8).collect::<Vec<_>>();
+/// let mut wav = Cursor::new(Vec::new());
+/// write_wav_header(&mut wav, &fmt, samples.len() as u32).unwrap();
+/// wav.write_all(&samples).unwrap();
+///
+/// let mut player = Player::from_bytes(wav.into_inner()).unwrap();
+/// let mut out = MockOut::new(player.format());
+/// player.play_to(&mut out).unwrap();
+/// assert_eq!(out.written(), &samples[..]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct MockOut {
+    fmt: Format,
+    written: Vec<u8>,
+    write_lengths: Vec<usize>,
+    volume: (f32, f32),
+    paused: bool,
+}
+
+impl MockOut {
+    /// Create a new mock device which pretends to play data in the given format.
+    pub fn new(fmt: Format) -> Self {
+        Self {
+            fmt,
+            written: Vec::new(),
+            write_lengths: Vec::new(),
+            volume: (1.0, 1.0),
+            paused: false,
+        }
+    }
+
+    /// Format the mock device was created with.
+    pub fn format(&self) -> Format {
+        self.fmt
+    }
+
+    /// All the data written so far, in order.
+    pub fn written(&self) -> &[u8] {
+        &self.written
+    }
+
+    /// Length of each write made so far, in order, which shows how the data was split.
+    ///
+    /// ```
+    /// use winaudio::wave::testing::MockOut;
+    /// use winaudio::wave::{Format, WaveOut};
+    ///
+    /// let mut out = MockOut::new(Format::pcm(1, 8000, 8));
+    /// out.write_samples(&[128; 10]).unwrap();
+    /// out.write_samples(&[128; 4]).unwrap();
+    /// assert_eq!(out.write_lengths(), &[10, 4]);
+    /// ```
+    pub fn write_lengths(&self) -> &[usize] {
+        &self.write_lengths
+    }
+
+    /// Last volume set, as `(left, right)`.
+    pub fn volume(&self) -> (f32, f32) {
+        self.volume
+    }
+
+    /// Whether the mock device is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+}
+
+impl WaveOut for MockOut {
+    fn write_samples(&mut self, data: &[u8]) -> Result<(), Error> {
+        // A real device would never finish playing the data while paused.
+        if self.paused {
+            return Err(Error::HandleBusy);
+        }
+        self.written.extend_from_slice(data);
+        self.write_lengths.push(data.len());
+        Ok(())
+    }
+
+    fn set_volume(&mut self, left: f32, right: f32) -> Result<(), Error> {
+        if !(0.0..=1.0).contains(&left) || !(0.0..=1.0).contains(&right) {
+            return Err(Error::InvalidParam);
+        }
+        self.volume = (left, right);
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<(), Error> {
+        self.paused = true;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        self.paused = false;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), Error> {
+        // Like `Out::stop`, stopping also ends the pause.
+        self.paused = false;
+        Ok(())
+    }
+
+    fn wait(&self) {}
+}
@@ -0,0 +1,60 @@
+//! Generation of simple test signals.
+use crate::wave::format::Tag;
+use crate::wave::Format;
+use std::f64::consts::PI;
+use std::time::Duration;
+
+/// Generate a sine wave of the given frequency and duration at full scale, encoded in the given
+/// format, with the same signal in every channel. The duration is rounded down to a whole
+/// number of frames.
+///
+/// PCM data of 8, 16, 24 or 32 bits and IEEE float data of 32 or 64 bits is supported. Any
+/// other format produces no data.
+///
+/// ```
+/// use std::time::Duration;
+/// use winaudio::wave::{tone, Format};
+///
+/// let fmt = Format::pcm(2, 44100, 16);
+/// let a4 = tone(440.0, Duration::from_secs(1), &fmt);
+/// assert_eq!(a4.len(), 44100 * 4);
+/// assert_eq!(&a4[..4], &[0, 0, 0, 0]);
+/// ```
+pub fn tone(freq_hz: f32, duration: Duration, fmt: &Format) -> Vec<u8> {
+    let supported = matches!(
+        (fmt.format_tag, fmt.bits_per_sample),
+        (Tag::Pcm, 8 | 16 | 24 | 32) | (Tag::IeeeFloat, 32 | 64)
+    );
+    if !supported || fmt.channels == 0 || fmt.samples_per_sec == 0 {
+        return Vec::new();
+    }
+
+    let frames = (duration.as_secs_f64() * fmt.samples_per_sec as f64) as usize;
+    let step = 2.0 * PI * freq_hz as f64 / fmt.samples_per_sec as f64;
+    let mut data = Vec::with_capacity(frames * fmt.block_align as usize);
+    for frame in 0..frames {
+        let value = (frame as f64 * step).sin();
+        for _ in 0..fmt.channels {
+            encode(value, fmt, &mut data);
+        }
+    }
+    data
+}
+
+/// Append a sample in the `-1.0..=1.0` range encoded in the given format, which must be one of
+/// the formats supported by `tone`.
+fn encode(value: f64, fmt: &Format, data: &mut Vec<u8>) {
+    match (fmt.format_tag, fmt.bits_per_sample) {
+        (Tag::Pcm, 8) => data.push((128.0 + value * 127.0).round() as u8),
+        (Tag::Pcm, 16) => data.extend_from_slice(&((value * 32767.0).round() as i16).to_le_bytes()),
+        (Tag::Pcm, 24) => {
+            let sample = (value * 8388607.0).round() as i32;
+            data.extend_from_slice(&sample.to_le_bytes()[..3]);
+        }
+        (Tag::Pcm, 32) => {
+            data.extend_from_slice(&((value * 2147483647.0).round() as i32).to_le_bytes())
+        }
+        (Tag::IeeeFloat, 32) => data.extend_from_slice(&(value as f32).to_le_bytes()),
+        _ => data.extend_from_slice(&value.to_le_bytes()),
+    }
+}